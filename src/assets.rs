@@ -0,0 +1,275 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use glium::backend::Facade;
+use glium::texture::CompressedSrgbTexture2d;
+
+use textureatlas;
+use textureatlas::{AtlasError, TextureAtlas};
+use tilemap::{MapData, MapError};
+
+#[derive(Debug)]
+pub enum AssetError {
+    Atlas(PathBuf, AtlasError),
+    Map(PathBuf, MapError)
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AssetError::Atlas(ref path, ref err) => write!(f, "Could not load `{}`: {}", path.display(), err),
+            AssetError::Map(ref path, ref err) => write!(f, "Could not load `{}`: {}", path.display(), err),
+        }
+    }
+}
+
+/// Loads and caches textures, atlases and maps so that
+/// each file is only loaded once and can be shared.
+///
+/// Assets can be requested by path or by an id that has
+/// been given to a path with `register`. Everything is
+/// handed out behind an `Rc` so a texture used by several
+/// atlases, or an atlas used by both a TileMap and a
+/// Scene, only lives on the GPU once.
+pub struct Assets<F> {
+    display: F,
+    ids: HashMap<String, PathBuf>,
+    textures: HashMap<PathBuf, Rc<CompressedSrgbTexture2d>>,
+    atlases: HashMap<PathBuf, Rc<TextureAtlas>>,
+    /// The images used by each atlas, so that atlases can be
+    /// rebuilt when one of their pages changes.
+    atlas_images: HashMap<PathBuf, Vec<PathBuf>>,
+    /// Atlases whose images were given to `atlas_with_image`
+    /// rather than listed in their JSON.
+    given_images: HashSet<PathBuf>,
+    maps: HashMap<PathBuf, Rc<MapData>>
+}
+
+/// An asset that was replaced by `Assets::reload`.
+///
+/// Anything holding on to `old` should swap it for `new`.
+pub enum Reloaded {
+    Atlas {
+        old: Rc<TextureAtlas>,
+        new: Rc<TextureAtlas>
+    },
+    Map {
+        old: Rc<MapData>,
+        new: Rc<MapData>
+    }
+}
+
+impl<F: Facade + Clone> Assets<F> {
+    pub fn new(display: &F) -> Assets<F> {
+        Assets {
+            display: display.clone(),
+            ids: HashMap::new(),
+            textures: HashMap::new(),
+            atlases: HashMap::new(),
+            atlas_images: HashMap::new(),
+            given_images: HashSet::new(),
+            maps: HashMap::new()
+        }
+    }
+
+    /// Give a path an id that can be used in its place.
+    pub fn register<P: AsRef<Path>>(&mut self, id: &str, path: P) {
+        self.ids.insert(id.to_string(), path.as_ref().to_path_buf());
+    }
+
+    /// Turn an id or path into the key used for caching, so
+    /// that different spellings of the same file are shared.
+    fn resolve<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let path = path.as_ref();
+        let path = path.to_str()
+            .and_then(|x| self.ids.get(x))
+            .map(|x| x.as_path())
+            .unwrap_or(path);
+        fs::canonicalize(path).unwrap_or(path.to_path_buf())
+    }
+
+    /// Load an image as a texture.
+    pub fn texture<P: AsRef<Path>>(&mut self, path: P) -> Result<Rc<CompressedSrgbTexture2d>, AssetError> {
+        let key = self.resolve(path);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+
+        let texture = Rc::new(try!(textureatlas::load_texture(&key, &self.display)
+            .map_err(|err| AssetError::Atlas(key.clone(), err))));
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    /// Load an atlas written by the `pack` tool from its JSON,
+    /// along with every page image that it lists.
+    pub fn atlas<P: AsRef<Path>>(&mut self, path: P) -> Result<Rc<TextureAtlas>, AssetError> {
+        let key = self.resolve(path);
+        if let Some(atlas) = self.atlases.get(&key) {
+            return Ok(atlas.clone());
+        }
+
+        let images = try!(textureatlas::page_paths(&key)
+            .map_err(|err| AssetError::Atlas(key.clone(), err)));
+        self.atlas_with_images(images, key, false)
+    }
+
+    /// Load an atlas whose image isn't next to its JSON.
+    pub fn atlas_with_image<P, Q>(&mut self, image_path: P, json_path: Q) -> Result<Rc<TextureAtlas>, AssetError>
+        where P: AsRef<Path>,
+              Q: AsRef<Path> {
+        self.atlas_with_images(vec![image_path.as_ref().to_path_buf()], json_path, true)
+    }
+
+    fn atlas_with_images<P>(&mut self, image_paths: Vec<PathBuf>, json_path: P, given: bool) -> Result<Rc<TextureAtlas>, AssetError>
+        where P: AsRef<Path> {
+        let key = self.resolve(json_path);
+        if let Some(atlas) = self.atlases.get(&key) {
+            return Ok(atlas.clone());
+        }
+
+        let mut images = Vec::with_capacity(image_paths.len());
+        let mut pages = Vec::with_capacity(image_paths.len());
+        for path in image_paths {
+            let image = self.resolve(path);
+            pages.push(try!(self.texture(&image)));
+            images.push(image);
+        }
+        let atlas = Rc::new(try!(TextureAtlas::from_json_pages(pages, &key)
+            .map_err(|err| AssetError::Atlas(key.clone(), err))));
+        self.atlas_images.insert(key.clone(), images);
+        if given {
+            self.given_images.insert(key.clone());
+        }
+        self.atlases.insert(key, atlas.clone());
+        Ok(atlas)
+    }
+
+    /// Load a map file.
+    ///
+    /// The map's atlas is not loaded here, use `atlas` with
+    /// `MapData::atlas` to get it.
+    pub fn map<P: AsRef<Path>>(&mut self, path: P) -> Result<Rc<MapData>, AssetError> {
+        let key = self.resolve(path);
+        if let Some(map) = self.maps.get(&key) {
+            return Ok(map.clone());
+        }
+
+        let map = Rc::new(try!(MapData::load(&key)
+            .map_err(|err| AssetError::Map(key.clone(), err))));
+        self.maps.insert(key, map.clone());
+        Ok(map)
+    }
+
+    /// Every file that a cached asset was loaded from.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.textures.keys()
+            .chain(self.atlases.keys())
+            .chain(self.maps.keys())
+            .cloned()
+            .collect()
+    }
+
+    /// Load a file again after it has changed on disk.
+    ///
+    /// Cached assets are replaced rather than modified, so the
+    /// returned list says which old handles need to be swapped
+    /// for new ones. Changing an image rebuilds every atlas that
+    /// uses it, and changing an atlas picks up any pages added
+    /// to it. If loading fails the old assets are kept.
+    pub fn reload<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<Reloaded>, AssetError> {
+        let key = self.resolve(path);
+
+        // everything is loaded before anything is replaced so
+        // that a failure part way through leaves the cache as
+        // it was
+        let mut texture = None;
+        let mut stale = Vec::new();
+        if self.textures.contains_key(&key) {
+            texture = Some(Rc::new(try!(textureatlas::load_texture(&key, &self.display)
+                .map_err(|err| AssetError::Atlas(key.clone(), err)))));
+            for (json, images) in self.atlas_images.iter() {
+                if images.contains(&key) {
+                    stale.push(json.clone());
+                }
+            }
+        }
+        if self.atlases.contains_key(&key) {
+            stale.push(key.clone());
+        }
+
+        let mut atlases = Vec::with_capacity(stale.len());
+        let mut textures = HashMap::new();
+        for json in stale {
+            // the atlas itself changed, so its pages may have too
+            let images: Vec<PathBuf> = if json == key && !self.given_images.contains(&json) {
+                try!(textureatlas::page_paths(&json)
+                    .map_err(|err| AssetError::Atlas(json.clone(), err)))
+                    .into_iter()
+                    .map(|x| self.resolve(x))
+                    .collect()
+            } else {
+                self.atlas_images[&json].clone()
+            };
+
+            let mut pages = Vec::with_capacity(images.len());
+            for image in images.iter() {
+                let page = match texture {
+                    Some(ref texture) if *image == key => texture.clone(),
+                    _ => match self.textures.get(image).or(textures.get(image)) {
+                        Some(page) => page.clone(),
+                        None => {
+                            let page = Rc::new(try!(textureatlas::load_texture(image, &self.display)
+                                .map_err(|err| AssetError::Atlas(image.clone(), err))));
+                            textures.insert(image.clone(), page.clone());
+                            page
+                        }
+                    }
+                };
+                pages.push(page);
+            }
+            let atlas = Rc::new(try!(TextureAtlas::from_json_pages(pages, &json)
+                .map_err(|err| AssetError::Atlas(json.clone(), err))));
+            atlases.push((json, images, atlas));
+        }
+
+        let map = if self.maps.contains_key(&key) {
+            Some(Rc::new(try!(MapData::load(&key)
+                .map_err(|err| AssetError::Map(key.clone(), err)))))
+        } else {
+            None
+        };
+
+        let mut reloaded = Vec::new();
+        if let Some(texture) = texture {
+            self.textures.insert(key.clone(), texture);
+        }
+        self.textures.extend(textures);
+        for (json, images, atlas) in atlases {
+            self.atlas_images.insert(json.clone(), images);
+            if let Some(old) = self.atlases.insert(json, atlas.clone()) {
+                reloaded.push(Reloaded::Atlas { old: old, new: atlas });
+            }
+        }
+        if let Some(map) = map {
+            if let Some(old) = self.maps.insert(key, map.clone()) {
+                reloaded.push(Reloaded::Map { old: old, new: map });
+            }
+        }
+        Ok(reloaded)
+    }
+
+    /// Drop any cached assets that are no longer used
+    /// anywhere else.
+    pub fn collect(&mut self) {
+        self.atlases = self.atlases.drain().filter(|&(_, ref x)| Rc::strong_count(x) > 1).collect();
+        self.maps = self.maps.drain().filter(|&(_, ref x)| Rc::strong_count(x) > 1).collect();
+        self.textures = self.textures.drain().filter(|&(_, ref x)| Rc::strong_count(x) > 1).collect();
+        let atlases = &self.atlases;
+        self.atlas_images.retain(|json, _| atlases.contains_key(json));
+        self.given_images.retain(|json| atlases.contains_key(json));
+    }
+}
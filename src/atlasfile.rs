@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde_json::value::Value;
+
+/// A frame as it was packed, in pixels.
+///
+/// Every format that `pack` writes is read into these
+/// without any textures, so that the `pack` tool can share
+/// the parsing with `TextureAtlas`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Packed {
+    pub page: usize,
+    /// The area used on the page, which for a rotated frame
+    /// has its width and height swapped.
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    /// Whether the frame was turned 90 degrees clockwise.
+    pub rotated: bool,
+    /// Pixels trimmed from the left and top.
+    pub offset_x: u32,
+    pub offset_y: u32,
+    /// Size of the image before trimming.
+    pub source_w: u32,
+    pub source_h: u32
+}
+
+impl Packed {
+    /// The size of the frame the right way up.
+    pub fn upright_size(&self) -> (u32, u32) {
+        if self.rotated { (self.h, self.w) } else { (self.w, self.h) }
+    }
+}
+
+/// Read a list of exactly `n` whole numbers.
+fn parse_numbers(value: Option<&Value>, n: usize) -> Option<Vec<u32>> {
+    let values: Vec<u32> = match value.and_then(|x| x.as_array()) {
+        Some(array) => array.iter().filter_map(|x| x.as_u64()).map(|x| x as u32).collect(),
+        None => return None
+    };
+    if values.len() == n { Some(values) } else { None }
+}
+
+/// Parse a frame written by the `pack` tool.
+///
+/// Frames are either a plain `[x, y, w, h]` or an object
+/// that also says how the frame was trimmed and rotated:
+///
+/// ```json
+/// {
+///     "frame": [x, y, w, h],
+///     "rotated": false,
+///     "offset": [x, y],
+///     "source_size": [w, h]
+/// }
+/// ```
+///
+/// `frame` is the area of the texture used, so for a
+/// rotated frame its width and height are swapped. Frames
+/// of a multi-page atlas also have a `page` index.
+pub fn parse_frame(name: &str, value: &Value) -> Result<Packed, String> {
+    let rect = if value.as_array().is_some() { Some(value) } else { value.find("frame") };
+    let rect = try!(parse_numbers(rect, 4)
+        .ok_or(format!("frame `{}` should have a `frame` of [x, y, w, h]", name)));
+    let mut packed = Packed {
+        page: 0,
+        x: rect[0],
+        y: rect[1],
+        w: rect[2],
+        h: rect[3],
+        rotated: false,
+        offset_x: 0,
+        offset_y: 0,
+        source_w: rect[2],
+        source_h: rect[3]
+    };
+    if let Some(page) = value.find("page") {
+        packed.page = try!(page.as_u64()
+            .ok_or(format!("`page` of frame `{}` should be a number", name))) as usize;
+    }
+    if let Some(rotated) = value.find("rotated") {
+        packed.rotated = try!(rotated.as_boolean()
+            .ok_or(format!("`rotated` of frame `{}` should be a bool", name)));
+    }
+    let (w, h) = packed.upright_size();
+    packed.source_w = w;
+    packed.source_h = h;
+    if let Some(offset) = value.find("offset") {
+        let offset = try!(parse_numbers(Some(offset), 2)
+            .ok_or(format!("`offset` of frame `{}` should be [x, y]", name)));
+        packed.offset_x = offset[0];
+        packed.offset_y = offset[1];
+    }
+    if let Some(size) = value.find("source_size") {
+        let size = try!(parse_numbers(Some(size), 2)
+            .ok_or(format!("`source_size` of frame `{}` should be [w, h]", name)));
+        packed.source_w = size[0];
+        packed.source_h = size[1];
+    }
+    Ok(packed)
+}
+
+/// The images used by an atlas, given its path and JSON.
+///
+/// These are listed under `pages` relative to the JSON, or
+/// for older single page atlases are a PNG next to it with
+/// the same name.
+pub fn page_paths(json_path: &Path, data: &Value) -> Result<Vec<PathBuf>, String> {
+    let pages = match data.find("pages") {
+        Some(pages) => try!(pages.as_array()
+            .ok_or("`pages` should be an array".to_string())),
+        None => return Ok(vec![json_path.with_extension("png")])
+    };
+    let dir = json_path.parent().unwrap_or(Path::new(""));
+    let mut paths = Vec::with_capacity(pages.len());
+    for page in pages.iter() {
+        let page = try!(page.as_string()
+            .ok_or("pages must be strings".to_string()));
+        paths.push(dir.join(page));
+    }
+    Ok(paths)
+}
+
+/// Read a `{"x": .., "y": .., "w": .., "h": ..}` object, or
+/// just its width and height.
+fn parse_rect(value: Option<&Value>, keys: &[&str]) -> Option<Vec<u32>> {
+    let value = match value {
+        Some(value) => value,
+        None => return None
+    };
+    let mut numbers = Vec::with_capacity(keys.len());
+    for key in keys {
+        match value.find(key).and_then(|x| x.as_u64()) {
+            Some(number) => numbers.push(number as u32),
+            None => return None
+        }
+    }
+    Some(numbers)
+}
+
+/// Parse a frame in TexturePacker's JSON formats, where the
+/// size of a rotated frame is given the right way up.
+fn parse_texturepacker_frame(name: &str, value: &Value, page: usize) -> Result<Packed, String> {
+    let rect = try!(parse_rect(value.find("frame"), &["x", "y", "w", "h"])
+        .ok_or(format!("frame `{}` should have a `frame` of x, y, w and h", name)));
+    let rotated = value.find("rotated").and_then(|x| x.as_boolean()).unwrap_or(false);
+    let mut packed = Packed {
+        page: page,
+        x: rect[0],
+        y: rect[1],
+        w: if rotated { rect[3] } else { rect[2] },
+        h: if rotated { rect[2] } else { rect[3] },
+        rotated: rotated,
+        offset_x: 0,
+        offset_y: 0,
+        source_w: rect[2],
+        source_h: rect[3]
+    };
+    if let Some(offset) = parse_rect(value.find("spriteSourceSize"), &["x", "y"]) {
+        packed.offset_x = offset[0];
+        packed.offset_y = offset[1];
+    }
+    if let Some(size) = parse_rect(value.find("sourceSize"), &["w", "h"]) {
+        packed.source_w = size[0];
+        packed.source_h = size[1];
+    }
+    Ok(packed)
+}
+
+/// Parse the frames of one of TexturePacker's JSON files, in
+/// either its hash or array format, as being on `page`.
+pub fn parse_texturepacker(data: &Value, page: usize) -> Result<Vec<(String, Packed)>, String> {
+    let mut frames = Vec::new();
+    match data.find("frames") {
+        Some(&Value::Object(ref hash)) => for (name, frame) in hash.iter() {
+            frames.push((name.clone(), try!(parse_texturepacker_frame(name, frame, page))));
+        },
+        Some(&Value::Array(ref array)) => for frame in array.iter() {
+            let name = try!(frame.find("filename")
+                .and_then(|x| x.as_string())
+                .ok_or("frames should have a `filename`".to_string()));
+            frames.push((name.to_string(), try!(parse_texturepacker_frame(name, frame, page))));
+        },
+        _ => return Err("missing `frames` object or array".to_string())
+    }
+    Ok(frames)
+}
+
+/// Parse a pair of numbers written as `x, y` in libGDX
+/// atlases.
+fn parse_pair(value: &str) -> Option<(u32, u32)> {
+    let mut parts = value.split(',').map(|x| x.trim().parse::<u32>().ok());
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Some(x)), Some(Some(y)), None) => Some((x, y)),
+        _ => None
+    }
+}
+
+/// Settings that follow the image name of a page in a
+/// libGDX atlas, unlike those of a frame these aren't
+/// indented.
+const LIBGDX_PAGE_SETTINGS: &'static [&'static str] = &["size", "format", "filter", "repeat", "pma"];
+
+/// Build a frame from the settings of a region in a libGDX
+/// atlas.
+fn libgdx_frame(name: &str, values: &HashMap<String, String>, page: usize) -> Result<Packed, String> {
+    let pair = |key: &str| {
+        values.get(key)
+            .and_then(|x| parse_pair(x))
+            .ok_or(format!("frame `{}` should have `{}: x, y`", name, key))
+    };
+    if values.get("rotate").map(|x| x != "false").unwrap_or(false) {
+        return Err(format!("frame `{}` is rotated, which isn't supported", name));
+    }
+    let (x, y) = try!(pair("xy"));
+    let (w, h) = try!(pair("size"));
+    let mut packed = Packed {
+        page: page,
+        x: x,
+        y: y,
+        w: w,
+        h: h,
+        rotated: false,
+        offset_x: 0,
+        offset_y: 0,
+        source_w: w,
+        source_h: h
+    };
+    if let (Ok((source_w, source_h)), Ok((offset_x, offset_y))) = (pair("orig"), pair("offset")) {
+        // libGDX measures the offset up from the bottom
+        let offset_y = try!(source_h.checked_sub(h).and_then(|x| x.checked_sub(offset_y))
+            .ok_or(format!("frame `{}` has an offset outside of its original size", name)));
+        packed.source_w = source_w;
+        packed.source_h = source_h;
+        packed.offset_x = offset_x;
+        packed.offset_y = offset_y;
+    }
+    Ok(packed)
+}
+
+/// Parse a libGDX `.atlas` file into the image names of its
+/// pages and its frames.
+///
+/// A blank line starts a page, whose image name is followed
+/// by its settings and then the frames on it, each a name
+/// followed by indented `key: value` settings. libGDX turns
+/// rotated frames anti-clockwise, which isn't supported.
+pub fn parse_libgdx(text: &str) -> Result<(Vec<String>, Vec<(String, Packed)>), String> {
+    let mut pages: Vec<String> = Vec::new();
+    let mut frames = Vec::new();
+    let mut frame: Option<(String, HashMap<String, String>)> = None;
+    let mut new_page = true;
+    for line in text.lines().chain(Some("").into_iter()) {
+        let indented = line.starts_with(' ') || line.starts_with('\t');
+        let line = line.trim();
+        // a frame's name may contain `:` so only a known key
+        // before a page's first frame is taken as a setting
+        let key = line.splitn(2, ':').next().unwrap_or("").trim();
+        let is_page_setting = !indented && !new_page && frame.is_none() &&
+            line.contains(':') && LIBGDX_PAGE_SETTINGS.contains(&key);
+        if line.is_empty() || (!indented && !is_page_setting) {
+            if let Some((name, values)) = frame.take() {
+                frames.push((name.clone(), try!(libgdx_frame(&name, &values, pages.len() - 1))));
+            }
+        }
+
+        if line.is_empty() {
+            new_page = true;
+        } else if new_page {
+            pages.push(line.to_string());
+            new_page = false;
+        } else if is_page_setting {
+            // page sizes come from the images themselves
+        } else if !indented {
+            frame = Some((line.to_string(), HashMap::new()));
+        } else if let Some((_, ref mut values)) = frame {
+            let mut parts = line.splitn(2, ':');
+            if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                values.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+    }
+    if pages.is_empty() {
+        return Err("an atlas needs at least one page".to_string());
+    }
+    Ok((pages, frames))
+}
+
+/// Parse the CSV written by the `pack` tool into the image
+/// names of its pages and its frames.
+///
+/// The first line lists the pages, then there is a header
+/// and a row per frame of its name, page, `x,y,w,h`, whether
+/// it is rotated, its offset and its source size. Older CSVs
+/// without the `pages` line have no page names.
+pub fn parse_csv(text: &str) -> Result<(Vec<String>, Vec<(String, Packed)>), String> {
+    let mut lines = text.lines().peekable();
+    let mut pages = Vec::new();
+    if let Some(line) = lines.peek().cloned() {
+        if line.starts_with("pages,") {
+            pages = line["pages,".len()..].split(',').map(|x| x.trim().to_string()).collect();
+            lines.next();
+        }
+    }
+    // skip the header
+    lines.next();
+
+    let mut frames = Vec::new();
+    for line in lines.filter(|x| !x.trim().is_empty()) {
+        let fields: Vec<&str> = line.split(',').map(|x| x.trim()).collect();
+        if fields.len() != 11 {
+            return Err(format!("expected 11 fields in `{}`", line));
+        }
+        let name = fields[0];
+        let invalid = || format!("invalid frame `{}`", name);
+        let page = try!(fields[1].parse::<usize>().map_err(|_| invalid()));
+        let mut numbers = Vec::with_capacity(8);
+        for field in fields[2..6].iter().chain(fields[7..].iter()) {
+            numbers.push(try!(field.parse::<u32>().map_err(|_| invalid())));
+        }
+        let rotated = try!(fields[6].parse::<bool>().map_err(|_| invalid()));
+        frames.push((name.to_string(), Packed {
+            page: page,
+            x: numbers[0],
+            y: numbers[1],
+            w: numbers[2],
+            h: numbers[3],
+            rotated: rotated,
+            offset_x: numbers[4],
+            offset_y: numbers[5],
+            source_w: numbers[6],
+            source_h: numbers[7]
+        }));
+    }
+    Ok((pages, frames))
+}
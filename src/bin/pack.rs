@@ -1,14 +1,22 @@
 extern crate clap;
+extern crate glob;
 extern crate image;
 extern crate serde;
 extern crate serde_json;
 extern crate texture_packer;
 
 use std::collections::BTreeMap;
+use std::env;
+use std::fs;
 use std::fs::File;
-use std::path::Path;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
 
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches, SubCommand};
+use glob::Pattern;
+use image::{DynamicImage, GenericImage, ImageBuffer, Rgba, RgbaImage};
 use serde::ser::{Serialize};
 use serde_json::Value;
 use serde_json::ser::{Serializer};
@@ -17,12 +25,764 @@ use texture_packer::{TexturePacker, TexturePackerConfig};
 use texture_packer::exporter::ImageExporter;
 use texture_packer::importer::ImageImporter;
 
+use atlasfile::Packed;
+use fnv::Fnv;
+
+// shared with the game, which reads what `pack` writes
+#[path = "../atlasfile.rs"]
+#[allow(dead_code)]
+mod atlasfile;
+#[path = "../fnv.rs"]
+#[allow(dead_code)]
+mod fnv;
+
+/// Extensions of the files picked up from directories.
+const IMAGE_EXTENSIONS: &'static [&'static str] = &["png", "jpg", "jpeg", "gif", "bmp", "tga"];
+
+/// Find every file below `dir`, in a consistent order so
+/// that the same inputs always pack the same way.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        entries.push(try!(entry).path());
+    }
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            try!(walk(&path, files));
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The name of a frame, which is its path without the
+/// extension and always separated by `/`.
+fn frame_name(path: &Path) -> String {
+    let path = path.with_extension("");
+    let parts: Vec<String> = path.components()
+        .map(|x| x.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    parts.join("/")
+}
+
+/// Turn the inputs given on the command line into the
+/// images to pack, keyed by frame name.
+///
+/// Files are named by their stem. Directories are walked
+/// for images, which are named by their path relative to
+/// the directory and filtered by the include and exclude
+/// patterns. Two images with the same name is an error.
+fn collect_inputs(inputs: &[&str], include: &[Pattern], exclude: &[Pattern]) -> Result<BTreeMap<String, PathBuf>, String> {
+    let mut frames: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for input in inputs.iter().map(|x| Path::new(x)) {
+        let mut found = Vec::new();
+        if input.is_dir() {
+            let mut files = Vec::new();
+            try!(walk(input, &mut files)
+                .map_err(|err| format!("Could not read `{}`: {}", input.display(), err)));
+            for path in files {
+                let is_image = path.extension()
+                    .and_then(|x| x.to_str())
+                    .map(|x| IMAGE_EXTENSIONS.contains(&&x.to_lowercase()[..]))
+                    .unwrap_or(false);
+                let relative = path.strip_prefix(input).unwrap().to_path_buf();
+                let name = frame_name(&relative);
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                let included = include.is_empty() || include.iter().any(|x| x.matches(&relative));
+                let excluded = exclude.iter().any(|x| x.matches(&relative));
+                if is_image && included && !excluded {
+                    found.push((name, path));
+                }
+            }
+        } else {
+            let name = try!(input.file_stem()
+                .and_then(|x| x.to_str())
+                .ok_or(format!("`{}` is not a file", input.display())));
+            found.push((name.to_string(), input.to_path_buf()));
+        }
+
+        for (name, path) in found {
+            if let Some(other) = frames.get(&name) {
+                return Err(format!(
+                    "Frame name `{}` is used by both `{}` and `{}`",
+                    name, other.display(), path.display()));
+            }
+            frames.insert(name, path);
+        }
+    }
+    Ok(frames)
+}
+
+/// How a spritesheet is divided into a uniform grid of
+/// cells, all in pixels.
+#[derive(Copy, Clone, Debug)]
+struct Grid {
+    cell_width: u32,
+    cell_height: u32,
+    /// Space around the outside of the grid.
+    margin: u32,
+    /// Space between neighbouring cells.
+    spacing: u32
+}
+
+/// Parse a size given as `<width>x<height>`, or a single
+/// number for a square.
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let mut parts = value.splitn(2, 'x');
+    let width = parts.next().and_then(|x| x.parse::<u32>().ok());
+    let height = match parts.next() {
+        Some(height) => height.parse::<u32>().ok(),
+        None => width
+    };
+    match (width, height) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => Some((width, height)),
+        _ => None
+    }
+}
+
+/// Read cell names from a CSV with a row of names for each
+/// row of the grid. Blank names leave a cell unnamed.
+fn load_names(path: &Path) -> Result<Vec<Vec<String>>, String> {
+    let mut file = try!(File::open(path)
+        .map_err(|err| format!("Could not open `{}`: {}", path.display(), err)));
+    let mut csv = String::new();
+    try!(file.read_to_string(&mut csv)
+        .map_err(|err| format!("Could not read `{}`: {}", path.display(), err)));
+    Ok(csv.lines()
+        .map(|line| line.split(',').map(|x| x.trim().to_string()).collect())
+        .collect())
+}
+
+fn is_empty(image: &DynamicImage) -> bool {
+    image.to_rgba().pixels().all(|x| x.data[3] == 0)
+}
+
+/// Crop away the fully transparent edges of an image,
+/// returning the cropped image and the position of its top
+/// left corner in the original. Images with nothing in them
+/// are left as they are.
+fn trim_image(image: &DynamicImage) -> (DynamicImage, u32, u32) {
+    let rgba = image.to_rgba();
+    let (width, height) = rgba.dimensions();
+    let (mut x1, mut y1, mut x2, mut y2) = (width, height, 0, 0);
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        if pixel.data[3] != 0 {
+            x1 = x1.min(x);
+            y1 = y1.min(y);
+            x2 = x2.max(x + 1);
+            y2 = y2.max(y + 1);
+        }
+    }
+    if x1 >= x2 || y1 >= y2 {
+        return (image.clone(), 0, 0);
+    }
+    let mut image = image.clone();
+    (image.crop(x1, y1, x2 - x1, y2 - y1), x1, y1)
+}
+
+/// Surround an image with `n` copies of its edge pixels so
+/// that filtering at the edge of a frame never picks up its
+/// neighbours in the atlas.
+fn extrude(image: &DynamicImage, n: u32) -> DynamicImage {
+    let rgba = image.to_rgba();
+    let (width, height) = rgba.dimensions();
+    let extruded = ImageBuffer::from_fn(width + n * 2, height + n * 2, |x, y| {
+        let x = (x as i64 - n as i64).max(0).min(width as i64 - 1) as u32;
+        let y = (y as i64 - n as i64).max(0).min(height as i64 - 1) as u32;
+        *rgba.get_pixel(x, y)
+    });
+    DynamicImage::ImageRgba8(extruded)
+}
+
+/// The row, column and top left corner of every whole cell
+/// that fits in a sheet of the given size, row by row.
+fn grid_cells(grid: &Grid, width: u32, height: u32) -> Vec<(u32, u32, u32, u32)> {
+    let columns = (width.saturating_sub(grid.margin * 2) + grid.spacing) / (grid.cell_width + grid.spacing);
+    let rows = (height.saturating_sub(grid.margin * 2) + grid.spacing) / (grid.cell_height + grid.spacing);
+    let mut cells = Vec::with_capacity((rows * columns) as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            let x = grid.margin + column * (grid.cell_width + grid.spacing);
+            let y = grid.margin + row * (grid.cell_height + grid.spacing);
+            cells.push((row, column, x, y));
+        }
+    }
+    cells
+}
+
+/// Cut a spritesheet into a frame for each cell that has
+/// anything in it.
+///
+/// Cells are named `<sheet>_<row>_<column>` after the file
+/// stem of the sheet, unless there is a CSV with the same
+/// stem next to it, in which case names are taken from that
+/// and cells without a name are skipped.
+fn slice_sheet(path: &Path, grid: &Grid) -> Result<Vec<(String, DynamicImage)>, String> {
+    let mut sheet = try!(image::open(path)
+        .map_err(|err| format!("Could not load `{}`: {}", path.display(), err)));
+    let stem = try!(path.file_stem()
+        .and_then(|x| x.to_str())
+        .ok_or(format!("`{}` is not a file", path.display())));
+    let csv = path.with_extension("csv");
+    let names = if csv.is_file() { Some(try!(load_names(&csv))) } else { None };
+
+    let (width, height) = sheet.dimensions();
+    let mut cells = Vec::new();
+    for (row, column, x, y) in grid_cells(grid, width, height) {
+        let name = match names {
+            Some(ref names) => {
+                let name = names.get(row as usize).and_then(|x| x.get(column as usize));
+                match name {
+                    Some(name) if !name.is_empty() => name.clone(),
+                    _ => continue
+                }
+            },
+            None => format!("{}_{}_{}", stem, row, column)
+        };
+        let cell = sheet.crop(x, y, grid.cell_width, grid.cell_height);
+        if !is_empty(&cell) {
+            cells.push((name, cell));
+        }
+    }
+    Ok(cells)
+}
+
+/// Split a frame name with a numeric suffix, such as
+/// `walk_3`, into the animation it belongs to and its index.
+fn split_index(name: &str) -> Option<(&str, u32)> {
+    let prefix = name.trim_right_matches(|c: char| c.is_digit(10));
+    if prefix.len() == name.len() {
+        return None;
+    }
+    let index = match name[prefix.len()..].parse::<u32>() {
+        Ok(index) => index,
+        Err(_) => return None
+    };
+    let animation = prefix.trim_right_matches(|c: char| c == '_' || c == '-');
+    if animation.is_empty() {
+        None
+    } else {
+        Some((animation, index))
+    }
+}
+
+/// Group frames with numeric suffixes into animations,
+/// with the frames of each in order of their index.
+///
+/// A lone numbered frame, such as `tree2`, is not an animation.
+/// Two frames with the same index, such as `walk_1` and
+/// `walk_01`, are an error as their order would be arbitrary.
+fn find_animations<'a, I>(names: I) -> Result<BTreeMap<String, Vec<String>>, String>
+    where I: Iterator<Item=&'a String> {
+    let mut found: BTreeMap<String, Vec<(u32, String)>> = BTreeMap::new();
+    for name in names {
+        if let Some((animation, index)) = split_index(name) {
+            found.entry(animation.to_string()).or_insert(Vec::new()).push((index, name.clone()));
+        }
+    }
+
+    let mut animations = BTreeMap::new();
+    for (animation, mut frames) in found {
+        if frames.len() < 2 {
+            continue;
+        }
+        frames.sort();
+        for pair in frames.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(format!("Frames `{}` and `{}` of animation `{}` have the same index",
+                    pair[0].1, pair[1].1, animation));
+            }
+        }
+        animations.insert(animation, frames.into_iter().map(|x| x.1).collect());
+    }
+    Ok(animations)
+}
+
+/// Read the frame rate of each animation from a JSON file
+/// of animation names to frames per second.
+fn load_fps(path: &Path) -> Result<BTreeMap<String, f64>, String> {
+    let file = try!(File::open(path)
+        .map_err(|err| format!("Could not open `{}`: {}", path.display(), err)));
+    let data: Value = try!(serde_json::from_reader(file)
+        .map_err(|err| format!("Could not read `{}`: {}", path.display(), err)));
+    let object = try!(data.as_object()
+        .ok_or(format!("`{}` should map animation names to frames per second", path.display())));
+    let mut fps = BTreeMap::new();
+    for (name, value) in object.iter() {
+        let value = try!(value.as_f64()
+            .ok_or(format!("Frames per second of `{}` should be a number", name)));
+        fps.insert(name.clone(), value);
+    }
+    Ok(fps)
+}
+
+/// The description of the atlas to write.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    /// The JSON read by `TextureAtlas::from_json`.
+    Splore,
+    /// TexturePacker's JSON with frames keyed by name.
+    JsonHash,
+    /// TexturePacker's JSON with frames in an array.
+    JsonArray,
+    /// The libGDX `.atlas` text format.
+    Libgdx,
+    /// A row of comma separated values per frame.
+    Csv
+}
+
+impl Format {
+    fn parse(value: &str) -> Option<Format> {
+        match value {
+            "splore" => Some(Format::Splore),
+            "json-hash" => Some(Format::JsonHash),
+            "json-array" => Some(Format::JsonArray),
+            "libgdx" => Some(Format::Libgdx),
+            "csv" => Some(Format::Csv),
+            _ => None
+        }
+    }
+}
+
+fn write_json(path: &str, json: &Value) -> io::Result<()> {
+    let jsonfile = try!(File::create(path));
+    let mut serializer = Serializer::pretty(jsonfile);
+    json.serialize(&mut serializer)
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{:?}", err)))
+}
+
+fn write_splore(
+    output: &str,
+    pages: &[String],
+    frames: &BTreeMap<String, Packed>,
+    animations: &BTreeMap<String, Vec<String>>,
+    fps: &BTreeMap<String, f64>) -> io::Result<Vec<String>> {
+    let mut json = BTreeMap::new();
+    let mut frames_json = BTreeMap::new();
+    for (name, frame) in frames.iter() {
+        let mut entry = BTreeMap::new();
+        entry.insert("frame", to_value(&(frame.x, frame.y, frame.w, frame.h)));
+        entry.insert("rotated", to_value(&frame.rotated));
+        entry.insert("offset", to_value(&(frame.offset_x, frame.offset_y)));
+        entry.insert("source_size", to_value(&(frame.source_w, frame.source_h)));
+        entry.insert("page", to_value(&frame.page));
+        frames_json.insert(name.clone(), entry);
+    }
+    json.insert("pages", to_value(&pages));
+    json.insert("frames", to_value(&frames_json));
+
+    let mut animations_json = BTreeMap::new();
+    for (name, frames) in animations.iter() {
+        let mut entry = BTreeMap::new();
+        entry.insert("frames", to_value(frames));
+        if let Some(fps) = fps.get(name) {
+            entry.insert("fps", to_value(fps));
+        }
+        animations_json.insert(name.clone(), entry);
+    }
+    json.insert("animations", to_value(&animations_json));
+
+    let path = format!("{}.json", output);
+    try!(write_json(&path, &to_value(&json)));
+    Ok(vec![path])
+}
+
+fn rect_json(x: u32, y: u32, w: u32, h: u32) -> Value {
+    let mut rect = BTreeMap::new();
+    rect.insert("x", x);
+    rect.insert("y", y);
+    rect.insert("w", w);
+    rect.insert("h", h);
+    to_value(&rect)
+}
+
+fn size_json(w: u32, h: u32) -> Value {
+    let mut size = BTreeMap::new();
+    size.insert("w", w);
+    size.insert("h", h);
+    to_value(&size)
+}
+
+/// Write TexturePacker's JSON, which describes one image
+/// per file, so multiple pages get a file each named
+/// after their image.
+fn write_texturepacker(
+    output: &str,
+    pages: &[String],
+    page_sizes: &[(u32, u32)],
+    frames: &BTreeMap<String, Packed>,
+    array: bool) -> io::Result<Vec<String>> {
+    let mut written = Vec::new();
+    for (page, (image, &(width, height))) in pages.iter().zip(page_sizes.iter()).enumerate() {
+        let mut hash = BTreeMap::new();
+        let mut list = Vec::new();
+        for (name, frame) in frames.iter().filter(|x| x.1.page == page) {
+            // TexturePacker gives the upright size of a frame
+            let (w, h) = frame.upright_size();
+            let trimmed = w != frame.source_w || h != frame.source_h;
+            let mut entry = BTreeMap::new();
+            entry.insert("frame", rect_json(frame.x, frame.y, w, h));
+            entry.insert("rotated", to_value(&frame.rotated));
+            entry.insert("trimmed", to_value(&trimmed));
+            entry.insert("spriteSourceSize", rect_json(frame.offset_x, frame.offset_y, w, h));
+            entry.insert("sourceSize", size_json(frame.source_w, frame.source_h));
+            if array {
+                entry.insert("filename", to_value(name));
+                list.push(entry);
+            } else {
+                hash.insert(name.clone(), entry);
+            }
+        }
+
+        let mut meta = BTreeMap::new();
+        meta.insert("app", to_value(&"splore pack"));
+        meta.insert("image", to_value(image));
+        meta.insert("format", to_value(&"RGBA8888"));
+        meta.insert("size", size_json(width, height));
+        meta.insert("scale", to_value(&"1"));
+
+        let mut json = BTreeMap::new();
+        json.insert("frames", if array { to_value(&list) } else { to_value(&hash) });
+        json.insert("meta", to_value(&meta));
+
+        let path = if pages.len() == 1 {
+            format!("{}.json", output)
+        } else {
+            format!("{}_{}.json", output, page)
+        };
+        try!(write_json(&path, &to_value(&json)));
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Write the libGDX `.atlas` format. Rotation is disabled
+/// when packing for it, as libGDX turns rotated frames the
+/// other way.
+fn write_libgdx(
+    output: &str,
+    pages: &[String],
+    page_sizes: &[(u32, u32)],
+    frames: &BTreeMap<String, Packed>) -> io::Result<Vec<String>> {
+    let path = format!("{}.atlas", output);
+    let mut file = try!(File::create(&path));
+    for (page, (image, &(width, height))) in pages.iter().zip(page_sizes.iter()).enumerate() {
+        try!(write!(file, "\n{}\n", image));
+        try!(write!(file, "size: {},{}\n", width, height));
+        try!(write!(file, "format: RGBA8888\n"));
+        try!(write!(file, "filter: Nearest,Nearest\n"));
+        try!(write!(file, "repeat: none\n"));
+        for (name, frame) in frames.iter().filter(|x| x.1.page == page) {
+            let (w, h) = frame.upright_size();
+            // libGDX measures the offset up from the bottom
+            let offset_y = frame.source_h - h - frame.offset_y;
+            try!(write!(file, "{}\n", name));
+            try!(write!(file, "  rotate: {}\n", frame.rotated));
+            try!(write!(file, "  xy: {}, {}\n", frame.x, frame.y));
+            try!(write!(file, "  size: {}, {}\n", w, h));
+            try!(write!(file, "  orig: {}, {}\n", frame.source_w, frame.source_h));
+            try!(write!(file, "  offset: {}, {}\n", frame.offset_x, offset_y));
+            try!(write!(file, "  index: -1\n"));
+        }
+    }
+    Ok(vec![path])
+}
+
+/// Write a line listing the page images, then a header and
+/// a row per frame, with the frame's area on its page as in
+/// the splore JSON.
+///
+/// Fields aren't quoted, so names with commas are rejected
+/// by `pack` before anything is written.
+fn write_csv(output: &str, pages: &[String], frames: &BTreeMap<String, Packed>) -> io::Result<Vec<String>> {
+    let path = format!("{}.csv", output);
+    let mut file = try!(File::create(&path));
+    try!(write!(file, "pages,{}\n", pages.join(",")));
+    try!(write!(file, "name,page,x,y,w,h,rotated,offset_x,offset_y,source_w,source_h\n"));
+    for (name, frame) in frames.iter() {
+        try!(write!(file, "{},{},{},{},{},{},{},{},{},{},{}\n",
+            name, frame.page, frame.x, frame.y, frame.w, frame.h, frame.rotated,
+            frame.offset_x, frame.offset_y, frame.source_w, frame.source_h));
+    }
+    Ok(vec![path])
+}
+
+fn hash_file(hasher: &mut Fnv, path: &Path) -> Result<(), String> {
+    let mut data = Vec::new();
+    try!(File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|err| format!("Could not read `{}`: {}", path.display(), err)));
+    hasher.write_str(&path.to_string_lossy());
+    hasher.write(&data);
+    Ok(())
+}
+
+/// Bumped whenever `pack` would write something different
+/// from the same inputs, so that atlases cached by an older
+/// version are packed again.
+const CACHE_FORMAT: u32 = 1;
+
+/// Hash the options and every file that goes into the atlas,
+/// including the names given to sheet cells and frame rates.
+fn hash_inputs(
+    options: &[String],
+    inputs: &BTreeMap<String, PathBuf>,
+    sheets: &[&str],
+    fps: Option<&str>) -> Result<u64, String> {
+    let mut hasher = Fnv::new();
+    hasher.write_u32(CACHE_FORMAT);
+    hasher.write_str(env!("CARGO_PKG_VERSION"));
+    for option in options.iter() {
+        hasher.write_str(option);
+    }
+    for (name, path) in inputs.iter() {
+        hasher.write_str(name);
+        try!(hash_file(&mut hasher, path));
+    }
+    for sheet in sheets.iter().map(|x| Path::new(x)) {
+        try!(hash_file(&mut hasher, sheet));
+        let csv = sheet.with_extension("csv");
+        if csv.is_file() {
+            try!(hash_file(&mut hasher, &csv));
+        }
+    }
+    if let Some(fps) = fps {
+        try!(hash_file(&mut hasher, Path::new(fps)));
+    }
+    Ok(hasher.finish())
+}
+
+/// What the last run packed, kept in `<output>.cache`.
+struct Cache {
+    hash: u64,
+    outputs: Vec<String>
+}
+
+impl Cache {
+    /// A missing or unreadable cache is treated the same as a
+    /// stale one.
+    fn load(path: &str) -> Option<Cache> {
+        let data: Value = match File::open(path).ok().and_then(|x| serde_json::from_reader(x).ok()) {
+            Some(data) => data,
+            None => return None
+        };
+        let hash = data.find("hash")
+            .and_then(|x| x.as_string())
+            .and_then(|x| u64::from_str_radix(x, 16).ok());
+        let outputs = data.find("outputs")
+            .and_then(|x| x.as_array())
+            .map(|x| x.iter().filter_map(|x| x.as_string()).map(|x| x.to_string()).collect());
+        match (hash, outputs) {
+            (Some(hash), Some(outputs)) => Some(Cache { hash: hash, outputs: outputs }),
+            _ => None
+        }
+    }
+
+    fn save(&self, path: &str) -> io::Result<()> {
+        let mut json = BTreeMap::new();
+        json.insert("hash", to_value(&format!("{:016x}", self.hash)));
+        json.insert("outputs", to_value(&self.outputs));
+        write_json(path, &to_value(&json))
+    }
+
+    /// Whether the outputs were packed from the same inputs
+    /// and are all still there.
+    fn is_fresh(&self, hash: u64) -> bool {
+        self.hash == hash && self.outputs.iter().all(|x| Path::new(x).is_file())
+    }
+}
+
+/// An atlas in the splore format, read back to be
+/// inspected, verified or unpacked.
+struct Atlas {
+    /// Path and pixels of each page.
+    pages: Vec<(PathBuf, RgbaImage)>,
+    frames: BTreeMap<String, Packed>
+}
+
+/// Load the JSON and pages of an atlas in the same way as
+/// `TextureAtlas::load` in the game.
+fn load_atlas(path: &Path) -> Result<Atlas, String> {
+    let file = try!(File::open(path)
+        .map_err(|err| format!("Could not open `{}`: {}", path.display(), err)));
+    let data: Value = try!(serde_json::from_reader(file)
+        .map_err(|err| format!("Could not read `{}`: {}", path.display(), err)));
+
+    let page_paths = try!(atlasfile::page_paths(path, &data)
+        .map_err(|err| format!("Invalid atlas `{}`: {}", path.display(), err)));
+    let mut pages = Vec::with_capacity(page_paths.len());
+    for page in page_paths {
+        let image = try!(image::open(&page)
+            .map_err(|err| format!("Could not load `{}`: {}", page.display(), err)));
+        pages.push((page, image.to_rgba()));
+    }
+
+    let object = try!(data.find("frames")
+        .and_then(|x| x.as_object())
+        .ok_or(format!("`{}` is missing its `frames` object", path.display())));
+    let mut frames = BTreeMap::new();
+    for (name, value) in object.iter() {
+        frames.insert(name.clone(), try!(atlasfile::parse_frame(name, value)
+            .map_err(|err| format!("Invalid atlas `{}`: {}", path.display(), err))));
+    }
+    Ok(Atlas { pages: pages, frames: frames })
+}
+
+/// Check that a frame lies within the page it is on.
+fn check_bounds(atlas: &Atlas, name: &str, frame: &Packed) -> Result<(), String> {
+    let (width, height) = match atlas.pages.get(frame.page) {
+        Some(&(_, ref image)) => image.dimensions(),
+        None => return Err(format!("`{}` is on missing page {}", name, frame.page))
+    };
+    if frame.x.saturating_add(frame.w) > width || frame.y.saturating_add(frame.h) > height {
+        return Err(format!("`{}` at [{}, {}, {}, {}] is outside page {}, which is {}x{}",
+            name, frame.x, frame.y, frame.w, frame.h, frame.page, width, height));
+    }
+    Ok(())
+}
+
+fn overlaps(a: &Packed, b: &Packed) -> bool {
+    a.page == b.page &&
+        a.x < b.x.saturating_add(b.w) && b.x < a.x.saturating_add(a.w) &&
+        a.y < b.y.saturating_add(b.h) && b.y < a.y.saturating_add(a.h)
+}
+
+/// Print how much of each page is taken up by frames.
+fn inspect(atlas: &Atlas) {
+    let percent = |used: u64, area: u64| if area == 0 { 0.0 } else { used as f64 * 100.0 / area as f64 };
+    let (mut total_used, mut total_area) = (0, 0);
+    for (page, &(ref path, ref image)) in atlas.pages.iter().enumerate() {
+        let (width, height) = image.dimensions();
+        let area = width as u64 * height as u64;
+        let frames: Vec<&Packed> = atlas.frames.values().filter(|x| x.page == page).collect();
+        let used = frames.iter().fold(0, |sum, x| sum + x.w as u64 * x.h as u64);
+        println!("Page {} `{}`: {}x{}, {} frames, {:.1}% occupied, {} pixels wasted",
+            page, path.display(), width, height, frames.len(),
+            percent(used, area), area.saturating_sub(used));
+        total_used += used;
+        total_area += area;
+    }
+    println!("{} frames on {} pages, {:.1}% occupied, {} pixels wasted",
+        atlas.frames.len(), atlas.pages.len(),
+        percent(total_used, total_area), total_area.saturating_sub(total_used));
+}
+
+/// Find every frame that is off its page or overlaps
+/// another frame.
+fn verify(atlas: &Atlas) -> Vec<String> {
+    let mut problems = Vec::new();
+    let frames: Vec<(&String, &Packed)> = atlas.frames.iter().collect();
+    for (i, &(name, frame)) in frames.iter().enumerate() {
+        if let Err(err) = check_bounds(atlas, name, frame) {
+            problems.push(err);
+        }
+        for &(other_name, other) in frames[i + 1..].iter() {
+            if overlaps(frame, other) {
+                problems.push(format!("`{}` overlaps `{}` on page {}", name, other_name, frame.page));
+            }
+        }
+    }
+    problems
+}
+
+/// Where `unpack` writes a frame. Frames named after nested
+/// directories are written back into them, but names that
+/// would escape `dir` are rejected.
+fn frame_path(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    let escapes = Path::new(name).components().any(|x| match x {
+        Component::ParentDir | Component::RootDir | Component::Prefix(_) => true,
+        _ => false
+    });
+    if escapes {
+        return Err(format!("`{}` can't be unpacked as it names a path outside the output directory", name));
+    }
+    Ok(dir.join(format!("{}.png", name)))
+}
+
+/// Copy a frame out of its page the right way up and with
+/// its trimmed edges put back.
+fn unpack_frame(page: &RgbaImage, frame: &Packed) -> RgbaImage {
+    let (w, h) = frame.upright_size();
+    ImageBuffer::from_fn(frame.source_w, frame.source_h, |x, y| {
+        if x < frame.offset_x || y < frame.offset_y || x - frame.offset_x >= w || y - frame.offset_y >= h {
+            return Rgba { data: [0, 0, 0, 0] };
+        }
+        let (x, y) = (x - frame.offset_x, y - frame.offset_y);
+        // rotated frames were turned clockwise, so the top
+        // left of the frame is at the top right on the page
+        if frame.rotated {
+            *page.get_pixel(frame.x + frame.w - 1 - y, frame.y + x)
+        } else {
+            *page.get_pixel(frame.x + x, frame.y + y)
+        }
+    })
+}
+
+/// Write each frame to `<dir>/<name>.png` the right way up and
+/// with its trimmed edges put back, returning how many were
+/// written.
+fn unpack(atlas: &Atlas, dir: &Path) -> Result<usize, String> {
+    // check every name before writing anything
+    let mut paths = Vec::with_capacity(atlas.frames.len());
+    for name in atlas.frames.keys() {
+        paths.push(try!(frame_path(dir, name)));
+    }
+
+    for ((name, frame), path) in atlas.frames.iter().zip(paths) {
+        try!(check_bounds(atlas, name, frame));
+        let pixels = unpack_frame(&atlas.pages[frame.page].1, frame);
+
+        if let Some(parent) = path.parent() {
+            try!(fs::create_dir_all(parent)
+                .map_err(|err| format!("Could not create `{}`: {}", parent.display(), err)));
+        }
+        let mut outfile = try!(File::create(&path)
+            .map_err(|err| format!("Could not create `{}`: {}", path.display(), err)));
+        try!(DynamicImage::ImageRgba8(pixels).save(&mut outfile, image::PNG)
+            .map_err(|err| format!("Could not write `{}`: {}", path.display(), err)));
+    }
+    Ok(atlas.frames.len())
+}
+
+fn parse_patterns(values: Option<Vec<&str>>) -> Vec<Pattern> {
+    values.unwrap_or(Vec::new()).iter().map(|x| {
+        Pattern::new(x).unwrap_or_else(|err| {
+            println!("Invalid pattern `{}`: {}", x, err);
+            process::exit(1);
+        })
+    }).collect()
+}
+
 fn main() {
     let matches = App::new("pack")
         .version("1.0")
+        .subcommands_negate_reqs(true)
+        .subcommand(SubCommand::with_name("inspect")
+            .about("Print the frame count, occupancy and wasted area of each page")
+            .arg(Arg::with_name("ATLAS")
+                .required(true)
+                .help("JSON of an atlas written by pack")))
+        .subcommand(SubCommand::with_name("verify")
+            .about("Check that every frame lies within its page and no two frames overlap")
+            .arg(Arg::with_name("ATLAS")
+                .required(true)
+                .help("JSON of an atlas written by pack")))
+        .subcommand(SubCommand::with_name("unpack")
+            .about("Extract each frame of an atlas into its own PNG")
+            .arg(Arg::with_name("ATLAS")
+                .required(true)
+                .help("JSON of an atlas written by pack"))
+            .arg(Arg::with_name("DIR")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .help("Directory to write frames to")))
         .arg(Arg::with_name("TEXTURES")
              .multiple(true)
-             .required(true))
+             .help("Images, or directories to search for images"))
         .arg(Arg::with_name("OUTPUT")
              .short("o")
              .long("output")
@@ -37,40 +797,636 @@ fn main() {
             .short("t")
             .long("trim")
             .required(false))
+        .arg(Arg::with_name("MAX_WIDTH")
+            .long("max-width")
+            .takes_value(true)
+            .help("Maximum width of each page in pixels")
+            .required(false))
+        .arg(Arg::with_name("MAX_HEIGHT")
+            .long("max-height")
+            .takes_value(true)
+            .help("Maximum height of each page in pixels")
+            .required(false))
+        .arg(Arg::with_name("INCLUDE")
+            .long("include")
+            .takes_value(true)
+            .multiple(true)
+            .help("Only pack images in directories whose relative path matches one of these globs")
+            .required(false))
+        .arg(Arg::with_name("EXCLUDE")
+            .long("exclude")
+            .takes_value(true)
+            .multiple(true)
+            .help("Skip images in directories whose relative path matches one of these globs")
+            .required(false))
+        .arg(Arg::with_name("SHEET")
+            .long("sheet")
+            .takes_value(true)
+            .multiple(true)
+            .requires("CELL")
+            .help("Spritesheet to slice into frames, named from a CSV next to it if there is one")
+            .required(false))
+        .arg(Arg::with_name("CELL")
+            .long("cell")
+            .takes_value(true)
+            .help("Size of each spritesheet cell as <width>x<height>")
+            .required(false))
+        .arg(Arg::with_name("MARGIN")
+            .long("margin")
+            .takes_value(true)
+            .help("Pixels around the outside of each spritesheet")
+            .required(false))
+        .arg(Arg::with_name("SPACING")
+            .long("spacing")
+            .takes_value(true)
+            .help("Pixels between spritesheet cells")
+            .required(false))
+        .arg(Arg::with_name("FPS")
+            .long("fps")
+            .takes_value(true)
+            .help("JSON file giving the frames per second of animations by name")
+            .required(false))
+        .arg(Arg::with_name("EXTRUDE")
+            .long("extrude")
+            .takes_value(true)
+            .help("Repeat the edge pixels of each frame this many times outward")
+            .required(false))
+        .arg(Arg::with_name("FORMAT")
+            .long("format")
+            .takes_value(true)
+            .help("Format of the atlas description: splore (default), json-hash, json-array, libgdx or csv")
+            .required(false))
+        .arg(Arg::with_name("ROTATE")
+            .long("rotate")
+            .help("Allow frames to be rotated to fit them in, except in libgdx atlases")
+            .required(false))
+        .arg(Arg::with_name("CHECK")
+            .long("check")
+            .help("Exit with an error if the outputs are out of date instead of packing")
+            .required(false))
         .get_matches();
 
-    let paths = matches.values_of("TEXTURES").expect("No textures given.");
+    match matches.subcommand() {
+        ("inspect", Some(matches)) => inspect(&open_atlas(matches)),
+        ("verify", Some(matches)) => {
+            let path = matches.value_of("ATLAS").unwrap();
+            let atlas = open_atlas(matches);
+            let problems = verify(&atlas);
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                process::exit(1);
+            }
+            println!("`{}` is OK: {} frames on {} pages", path, atlas.frames.len(), atlas.pages.len());
+        },
+        ("unpack", Some(matches)) => {
+            let dir = Path::new(matches.value_of("DIR").unwrap());
+            let count = unpack(&open_atlas(matches), dir).unwrap_or_else(|err| {
+                println!("{}", err);
+                process::exit(1);
+            });
+            println!("Unpacked {} frames into `{}`", count, dir.display());
+        },
+        _ => pack(&matches)
+    }
+}
+
+fn open_atlas(matches: &ArgMatches) -> Atlas {
+    load_atlas(Path::new(matches.value_of("ATLAS").unwrap())).unwrap_or_else(|err| {
+        println!("{}", err);
+        process::exit(1);
+    })
+}
+
+/// Pack images into an atlas, which is what `pack` does when
+/// not given a subcommand.
+fn pack(matches: &ArgMatches) {
+    let paths = matches.values_of("TEXTURES").unwrap_or(Vec::new());
+    let sheets = matches.values_of("SHEET").unwrap_or(Vec::new());
+    if paths.is_empty() && sheets.is_empty() {
+        println!("No textures or spritesheets given.");
+        process::exit(1);
+    }
     let output = matches.value_of("OUTPUT").expect("No output path given.");
     let border = matches.value_of("BORDER").unwrap_or("0").parse::<u32>().ok().expect("Border is not a u32.");
     let trim = matches.is_present("TRIM");
+    let extrusion = matches.value_of("EXTRUDE").unwrap_or("0").parse::<u32>().ok().expect("Extrude is not a u32.");
+    let format = Format::parse(matches.value_of("FORMAT").unwrap_or("splore")).unwrap_or_else(|| {
+        println!("Unknown format, expected splore, json-hash, json-array, libgdx or csv.");
+        process::exit(1);
+    });
+    let include = parse_patterns(matches.values_of("INCLUDE"));
+    let exclude = parse_patterns(matches.values_of("EXCLUDE"));
+    let inputs = collect_inputs(&paths, &include, &exclude).unwrap_or_else(|err| {
+        println!("{}", err);
+        process::exit(1);
+    });
+
+    // `--check` is left out so that checking agrees with
+    // the run that packed the outputs
+    let options: Vec<String> = env::args().skip(1).filter(|x| x != "--check").collect();
+    let hash = hash_inputs(&options, &inputs, &sheets, matches.value_of("FPS")).unwrap_or_else(|err| {
+        println!("{}", err);
+        process::exit(1);
+    });
+    let cache_path = format!("{}.cache", output);
+    let fresh = Cache::load(&cache_path).map(|x| x.is_fresh(hash)).unwrap_or(false);
+    if matches.is_present("CHECK") {
+        if !fresh {
+            println!("`{}` is out of date.", output);
+            process::exit(1);
+        }
+        return;
+    }
+    if fresh {
+        println!("`{}` is up to date.", output);
+        return;
+    }
+
+    let mut images = BTreeMap::new();
+    for (name, path) in inputs {
+        images.insert(name, ImageImporter::import_from_file(&path).unwrap());
+    }
+    if !sheets.is_empty() {
+        let (cell_width, cell_height) = matches.value_of("CELL")
+            .and_then(parse_size)
+            .expect("Cell size is not <width>x<height>.");
+        let grid = Grid {
+            cell_width: cell_width,
+            cell_height: cell_height,
+            margin: matches.value_of("MARGIN").unwrap_or("0").parse::<u32>().ok().expect("Margin is not a u32."),
+            spacing: matches.value_of("SPACING").unwrap_or("0").parse::<u32>().ok().expect("Spacing is not a u32.")
+        };
+        for sheet in sheets.iter().map(|x| Path::new(x)) {
+            let cells = slice_sheet(sheet, &grid).unwrap_or_else(|err| {
+                println!("{}", err);
+                process::exit(1);
+            });
+            for (name, cell) in cells {
+                if images.contains_key(&name) {
+                    println!("Frame name `{}` from `{}` is already in use", name, sheet.display());
+                    process::exit(1);
+                }
+                images.insert(name, cell);
+            }
+        }
+    }
+
+    let animations = find_animations(images.keys()).unwrap_or_else(|err| {
+        println!("{}", err);
+        process::exit(1);
+    });
+    let fps = matches.value_of("FPS").map(|x| load_fps(Path::new(x))).unwrap_or(Ok(BTreeMap::new()));
+    let fps = fps.unwrap_or_else(|err| {
+        println!("{}", err);
+        process::exit(1);
+    });
+    if format == Format::Csv {
+        if let Some(name) = images.keys().map(|x| &x[..]).chain(Some(output)).find(|x| x.contains(',')) {
+            println!("`{}` can't be written to a CSV atlas as it contains a comma", name);
+            process::exit(1);
+        }
+    }
+    for name in fps.keys() {
+        if !animations.contains_key(name) {
+            println!("Warning: frames per second given for unknown animation `{}`", name);
+        }
+    }
+
+    // extruded images are trimmed here instead of by the
+    // packer, which would otherwise trim into the extrusion
+    let mut sources = BTreeMap::new();
+    if extrusion > 0 {
+        for (name, image) in images.iter_mut() {
+            let (width, height) = image.dimensions();
+            let (trimmed, x, y) = if trim { trim_image(image) } else { (image.clone(), 0, 0) };
+            sources.insert(name.clone(), (x, y, width, height));
+            *image = extrude(&trimmed, extrusion);
+        }
+    }
 
     let mut cfg = TexturePackerConfig::default();
-    cfg.allow_rotation = false;
+    cfg.allow_rotation = matches.is_present("ROTATE") && format != Format::Libgdx;
     cfg.border_padding = border;
-    cfg.trim = trim;
+    cfg.trim = trim && extrusion == 0;
+    if let Some(width) = matches.value_of("MAX_WIDTH") {
+        cfg.max_width = width.parse::<u32>().ok().expect("Max width is not a u32.");
+    }
+    if let Some(height) = matches.value_of("MAX_HEIGHT") {
+        cfg.max_height = height.parse::<u32>().ok().expect("Max height is not a u32.");
+    }
 
-    let mut packer = TexturePacker::new_skyline(cfg);
-    for path in paths.iter().map(|x| Path::new(x)) {
-        let texture = ImageImporter::import_from_file(path).unwrap();
-        let name = path.file_stem().unwrap().to_os_string().into_string().unwrap();
-        packer.pack_own(name, texture);
+    // start a new page whenever a texture doesn't fit on
+    // the current one
+    let mut pages = vec![TexturePacker::new_skyline(cfg)];
+    for (name, texture) in images {
+        if !pages.last().unwrap().can_pack(&texture) {
+            pages.push(TexturePacker::new_skyline(cfg));
+            if !pages.last().unwrap().can_pack(&texture) {
+                println!("`{}` is larger than the maximum page size.", name);
+                process::exit(1);
+            }
+        }
+        pages.last_mut().unwrap().pack_own(name, texture);
     }
 
-    let packed = ImageExporter::export(&packer).unwrap();
+    // a single page keeps the name of the output so that
+    // it sits next to the JSON as before
     let output = Path::new(output);
-    let mut outfile = File::create(format!("{}.png", output.to_str().unwrap())).unwrap();
-
-    packed.save(&mut outfile, image::PNG).unwrap();
+    let page_names: Vec<String> = if pages.len() == 1 {
+        vec![format!("{}.png", output.to_str().unwrap())]
+    } else {
+        (0..pages.len()).map(|i| format!("{}_{}.png", output.to_str().unwrap(), i)).collect()
+    };
+    let mut page_sizes = Vec::with_capacity(pages.len());
+    for (packer, name) in pages.iter().zip(page_names.iter()) {
+        let packed = ImageExporter::export(packer).unwrap();
+        page_sizes.push(packed.dimensions());
+        let mut outfile = File::create(name).unwrap();
+        packed.save(&mut outfile, image::PNG).unwrap();
+    }
+    // pages are referred to relative to the output
+    let page_paths = page_names.clone();
+    let page_names: Vec<String> = page_names.iter()
+        .map(|x| Path::new(x).file_name().unwrap().to_str().unwrap().to_string())
+        .collect();
 
-    let mut json = BTreeMap::new();
     let mut frames = BTreeMap::new();
-    for (name, frame) in packer.get_frames().iter() {
-        frames.insert(name, (frame.frame.x, frame.frame.y, frame.frame.w, frame.frame.h));
+    for (page, packer) in pages.iter().enumerate() {
+        for (name, frame) in packer.get_frames().iter() {
+            // `source` holds the offset of the trimmed pixels and
+            // the size of the image before trimming
+            let mut packed = Packed {
+                page: page,
+                x: frame.frame.x,
+                y: frame.frame.y,
+                w: frame.frame.w,
+                h: frame.frame.h,
+                rotated: frame.rotated,
+                offset_x: frame.source.x,
+                offset_y: frame.source.y,
+                source_w: frame.source.w,
+                source_h: frame.source.h
+            };
+            // the frame is the image inside its extrusion
+            if let Some(&(x, y, width, height)) = sources.get(name) {
+                packed.x += extrusion;
+                packed.y += extrusion;
+                packed.w -= extrusion * 2;
+                packed.h -= extrusion * 2;
+                packed.offset_x = x;
+                packed.offset_y = y;
+                packed.source_w = width;
+                packed.source_h = height;
+            }
+            frames.insert(name.clone(), packed);
+        }
     }
-    json.insert("frames", to_value(&frames));
-    let json = to_value(&json);
 
-    let mut jsonfile = File::create(format!("{}.json", output.to_str().unwrap())).unwrap();
-    let mut serializer = Serializer::pretty(jsonfile);
-    json.serialize(&mut serializer);
-}
\ No newline at end of file
+    let output = output.to_str().unwrap();
+    let written = match format {
+        Format::Splore => write_splore(output, &page_names, &frames, &animations, &fps),
+        Format::JsonHash => write_texturepacker(output, &page_names, &page_sizes, &frames, false),
+        Format::JsonArray => write_texturepacker(output, &page_names, &page_sizes, &frames, true),
+        Format::Libgdx => write_libgdx(output, &page_names, &page_sizes, &frames),
+        Format::Csv => write_csv(output, &page_names, &frames),
+    };
+    let written = written.unwrap_or_else(|err| {
+        println!("Could not write `{}`: {}", output, err);
+        process::exit(1);
+    });
+
+    let cache = Cache {
+        hash: hash,
+        outputs: page_paths.into_iter().chain(written.into_iter()).collect()
+    };
+    cache.save(&cache_path).unwrap_or_else(|err| {
+        println!("Could not write `{}`: {}", cache_path, err);
+        process::exit(1);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+
+    use image::{DynamicImage, GenericImage, ImageBuffer, Rgba, RgbaImage};
+    use serde_json;
+    use serde_json::Value;
+
+    use atlasfile::{parse_csv, parse_frame, parse_libgdx, parse_texturepacker, Packed};
+    use super::*;
+
+    /// An empty directory to write files for a test into.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap();
+    }
+
+    fn packed(page: usize, x: u32, y: u32, w: u32, h: u32) -> Packed {
+        Packed {
+            page: page,
+            x: x,
+            y: y,
+            w: w,
+            h: h,
+            rotated: false,
+            offset_x: 0,
+            offset_y: 0,
+            source_w: w,
+            source_h: h
+        }
+    }
+
+    fn json(text: &str) -> Value {
+        serde_json::from_str(text).unwrap()
+    }
+
+    fn read(path: &str) -> String {
+        let mut text = String::new();
+        File::open(path).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    /// Frames on two pages, one trimmed and one rotated.
+    fn exported_frames(rotated: bool) -> BTreeMap<String, Packed> {
+        let mut trimmed = packed(0, 2, 3, 4, 5);
+        trimmed.offset_x = 1;
+        trimmed.offset_y = 2;
+        trimmed.source_w = 7;
+        trimmed.source_h = 8;
+        let mut turned = packed(1, 6, 0, 3, 2);
+        if rotated {
+            turned.rotated = true;
+            turned.source_w = 2;
+            turned.source_h = 3;
+        }
+        let mut frames = BTreeMap::new();
+        frames.insert("idle".to_string(), packed(0, 0, 0, 2, 2));
+        frames.insert("ui:button".to_string(), trimmed);
+        frames.insert("walk/0".to_string(), turned);
+        frames
+    }
+
+    fn sorted(frames: Vec<(String, Packed)>) -> BTreeMap<String, Packed> {
+        frames.into_iter().collect()
+    }
+
+    #[test]
+    fn texturepacker_exports_read_back() {
+        let dir = temp_dir("splore-pack-texturepacker");
+        let pages = vec!["atlas_0.png".to_string(), "atlas_1.png".to_string()];
+        let frames = exported_frames(true);
+        for &array in &[false, true] {
+            let output = dir.join(if array { "array" } else { "hash" });
+            let written = write_texturepacker(output.to_str().unwrap(), &pages, &[(16, 16), (16, 8)],
+                &frames, array).unwrap();
+            assert_eq!(written.len(), 2);
+            let mut read_back = Vec::new();
+            for (page, path) in written.iter().enumerate() {
+                read_back.extend(parse_texturepacker(&json(&read(path)), page).unwrap());
+            }
+            assert_eq!(sorted(read_back), frames);
+        }
+    }
+
+    #[test]
+    fn libgdx_exports_read_back() {
+        let dir = temp_dir("splore-pack-libgdx");
+        let output = dir.join("atlas");
+        let pages = vec!["atlas_0.png".to_string(), "atlas_1.png".to_string()];
+        let frames = exported_frames(false);
+        let written = write_libgdx(output.to_str().unwrap(), &pages, &[(16, 16), (16, 8)], &frames).unwrap();
+        let (names, read_back) = parse_libgdx(&read(&written[0])).unwrap();
+        assert_eq!(names, pages);
+        assert_eq!(sorted(read_back), frames);
+
+        // libGDX turns frames the other way
+        write_libgdx(output.to_str().unwrap(), &pages, &[(16, 16), (16, 8)], &exported_frames(true)).unwrap();
+        assert!(parse_libgdx(&read(&written[0])).is_err());
+    }
+
+    #[test]
+    fn csv_exports_read_back() {
+        let dir = temp_dir("splore-pack-csv");
+        let output = dir.join("atlas");
+        let pages = vec!["atlas_0.png".to_string(), "atlas_1.png".to_string()];
+        let frames = exported_frames(true);
+        let written = write_csv(output.to_str().unwrap(), &pages, &frames).unwrap();
+        let (names, read_back) = parse_csv(&read(&written[0])).unwrap();
+        assert_eq!(names, pages);
+        assert_eq!(sorted(read_back), frames);
+    }
+
+    #[test]
+    fn frame_names_are_separated_by_slashes() {
+        assert_eq!(frame_name(Path::new("hero.png")), "hero");
+        assert_eq!(frame_name(&Path::new("walk").join("left").join("0.png")), "walk/left/0");
+    }
+
+    #[test]
+    fn collects_images_from_directories() {
+        let dir = temp_dir("splore_pack_collect");
+        touch(&dir.join("sprites").join("hero.png"));
+        touch(&dir.join("sprites").join("walk").join("0.PNG"));
+        touch(&dir.join("sprites").join("notes.txt"));
+
+        let sprites = dir.join("sprites");
+        let inputs = collect_inputs(&[sprites.to_str().unwrap()], &[], &[]).unwrap();
+        let names: Vec<&String> = inputs.keys().collect();
+        assert_eq!(names, vec!["hero", "walk/0"]);
+    }
+
+    #[test]
+    fn rejects_duplicate_frame_names() {
+        let dir = temp_dir("splore_pack_duplicates");
+        touch(&dir.join("sprites").join("hero.png"));
+        touch(&dir.join("hero.png"));
+
+        let sprites = dir.join("sprites");
+        let hero = dir.join("hero.png");
+        let result = collect_inputs(&[sprites.to_str().unwrap(), hero.to_str().unwrap()], &[], &[]);
+        assert!(result.unwrap_err().contains("`hero`"));
+    }
+
+    #[test]
+    fn splits_numeric_suffixes() {
+        assert_eq!(split_index("walk_3"), Some(("walk", 3)));
+        assert_eq!(split_index("walk-12"), Some(("walk", 12)));
+        assert_eq!(split_index("tree2"), Some(("tree", 2)));
+        assert_eq!(split_index("walk"), None);
+        assert_eq!(split_index("42"), None);
+    }
+
+    #[test]
+    fn groups_numbered_frames_into_animations() {
+        let names: Vec<String> = ["walk_10", "walk_0", "walk_2", "tree2", "idle"]
+            .iter().map(|x| x.to_string()).collect();
+        let animations = find_animations(names.iter()).unwrap();
+        assert_eq!(animations.len(), 1);
+        assert_eq!(animations["walk"], vec!["walk_0", "walk_2", "walk_10"]);
+    }
+
+    #[test]
+    fn rejects_animations_with_duplicate_indices() {
+        let names: Vec<String> = ["walk_1", "walk_01", "walk_2"]
+            .iter().map(|x| x.to_string()).collect();
+        assert!(find_animations(names.iter()).is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("16x8"), Some((16, 8)));
+        assert_eq!(parse_size("16"), Some((16, 16)));
+        assert_eq!(parse_size("0x8"), None);
+        assert_eq!(parse_size("16x"), None);
+        assert_eq!(parse_size("ax8"), None);
+    }
+
+    #[test]
+    fn finds_whole_grid_cells() {
+        let grid = Grid { cell_width: 16, cell_height: 8, margin: 1, spacing: 2 };
+        // 1 + 16 + 2 + 16 + 1 wide, with a pixel to spare
+        assert_eq!(grid_cells(&grid, 37, 10), vec![(0, 0, 1, 1), (0, 1, 19, 1)]);
+        assert_eq!(grid_cells(&grid, 35, 20), vec![(0, 0, 1, 1), (1, 0, 1, 11)]);
+        assert_eq!(grid_cells(&grid, 17, 10), vec![]);
+    }
+
+    #[test]
+    fn trims_transparent_edges() {
+        let mut image = ImageBuffer::new(5, 4);
+        image.put_pixel(1, 1, Rgba { data: [255, 0, 0, 255] });
+        image.put_pixel(3, 2, Rgba { data: [0, 255, 0, 1] });
+        let (trimmed, x, y) = trim_image(&DynamicImage::ImageRgba8(image));
+        assert_eq!((x, y), (1, 1));
+        assert_eq!(trimmed.dimensions(), (3, 2));
+
+        let empty = DynamicImage::ImageRgba8(ImageBuffer::new(5, 4));
+        let (trimmed, x, y) = trim_image(&empty);
+        assert_eq!((x, y), (0, 0));
+        assert_eq!(trimmed.dimensions(), (5, 4));
+    }
+
+    #[test]
+    fn extrudes_edge_pixels() {
+        let red = Rgba { data: [255, 0, 0, 255] };
+        let blue = Rgba { data: [0, 0, 255, 255] };
+        let image = ImageBuffer::from_fn(2, 1, |x, _| if x == 0 { red } else { blue });
+        let extruded = extrude(&DynamicImage::ImageRgba8(image), 2).to_rgba();
+        assert_eq!(extruded.dimensions(), (6, 5));
+        for (x, y, pixel) in extruded.enumerate_pixels() {
+            assert_eq!(*pixel, if x < 3 { red } else { blue }, "at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn cache_is_fresh_while_outputs_exist() {
+        let dir = temp_dir("splore_pack_cache");
+        let output = dir.join("atlas.json");
+        touch(&output);
+
+        let cache = Cache { hash: 0xfedcba9876543210, outputs: vec![output.to_str().unwrap().to_string()] };
+        let path = dir.join("atlas.cache");
+        cache.save(path.to_str().unwrap()).unwrap();
+        let cache = Cache::load(path.to_str().unwrap()).unwrap();
+        assert!(cache.is_fresh(0xfedcba9876543210));
+        assert!(!cache.is_fresh(0xfedcba9876543211));
+
+        fs::remove_file(&output).unwrap();
+        assert!(!cache.is_fresh(0xfedcba9876543210));
+    }
+
+    #[test]
+    fn parses_packed_frames() {
+        assert_eq!(parse_frame("a", &json("[1, 2, 3, 4]")).unwrap(), packed(0, 1, 2, 3, 4));
+
+        let frame = parse_frame("b", &json(r#"{"frame": [1, 2, 3, 4], "rotated": true, "page": 2}"#)).unwrap();
+        assert_eq!(frame.page, 2);
+        assert!(frame.rotated);
+        assert_eq!((frame.source_w, frame.source_h), (4, 3));
+
+        let frame = parse_frame("c", &json(r#"{"frame": [1, 2, 3, 4], "offset": [5, 6], "source_size": [7, 8]}"#)).unwrap();
+        assert_eq!((frame.offset_x, frame.offset_y), (5, 6));
+        assert_eq!((frame.source_w, frame.source_h), (7, 8));
+
+        assert!(parse_frame("d", &json("[1, 2, 3]")).is_err());
+        assert!(parse_frame("e", &json(r#"{"frame": [1, 2, 3, 4], "rotated": 1}"#)).is_err());
+    }
+
+    #[test]
+    fn overlaps_only_on_the_same_page() {
+        let a = packed(0, 0, 0, 4, 4);
+        assert!(overlaps(&a, &packed(0, 3, 3, 4, 4)));
+        assert!(!overlaps(&a, &packed(0, 4, 0, 4, 4)));
+        assert!(!overlaps(&a, &packed(0, 0, 4, 4, 4)));
+        assert!(!overlaps(&a, &packed(1, 0, 0, 4, 4)));
+        assert!(!overlaps(&a, &packed(0, u32::max_value(), 0, 4, 4)));
+    }
+
+    #[test]
+    fn verify_reports_overlaps_and_frames_off_the_page() {
+        let mut frames = BTreeMap::new();
+        frames.insert("a".to_string(), packed(0, 0, 0, 4, 4));
+        frames.insert("b".to_string(), packed(0, 4, 0, 4, 4));
+        let mut atlas = Atlas { pages: vec![(PathBuf::from("page.png"), ImageBuffer::new(8, 8))], frames: frames };
+        assert!(verify(&atlas).is_empty());
+
+        atlas.frames.insert("c".to_string(), packed(0, 2, 2, 4, 4));
+        atlas.frames.insert("d".to_string(), packed(0, 6, 6, 4, 4));
+        atlas.frames.insert("e".to_string(), packed(1, 0, 0, 1, 1));
+        let problems = verify(&atlas);
+        // c overlaps a and b, d is off page 0 and e is on a missing page
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+    }
+
+    #[test]
+    fn unpacks_rotated_and_trimmed_frames() {
+        // a 3x2 image turned clockwise onto the page at (1, 1)
+        let (w, h) = (3, 2);
+        let pixel = |x: u32, y: u32| Rgba { data: [x as u8, y as u8, 0, 255] };
+        let mut page: RgbaImage = ImageBuffer::new(6, 6);
+        for y in 0..h {
+            for x in 0..w {
+                page.put_pixel(1 + h - 1 - y, 1 + x, pixel(x, y));
+            }
+        }
+
+        let mut frame = packed(0, 1, 1, h, w);
+        frame.rotated = true;
+        frame.offset_x = 1;
+        frame.offset_y = 2;
+        frame.source_w = 5;
+        frame.source_h = 4;
+        let unpacked = unpack_frame(&page, &frame);
+        assert_eq!(unpacked.dimensions(), (5, 4));
+        for (x, y, p) in unpacked.enumerate_pixels() {
+            if x >= 1 && x < 1 + w && y >= 2 && y < 2 + h {
+                assert_eq!(*p, pixel(x - 1, y - 2));
+            } else {
+                assert_eq!(p.data[3], 0);
+            }
+        }
+    }
+
+    #[test]
+    fn frame_paths_stay_inside_the_output() {
+        let dir = Path::new("out");
+        assert_eq!(frame_path(dir, "walk/0").unwrap(), dir.join("walk/0.png"));
+        assert!(frame_path(dir, "../escape").is_err());
+        assert!(frame_path(dir, "walk/../../escape").is_err());
+        assert!(frame_path(dir, "/etc/escape").is_err());
+    }
+}
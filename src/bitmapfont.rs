@@ -0,0 +1,497 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+use glium::texture::Texture;
+use na::Pnt3;
+
+use scene::Vertex;
+use textureatlas::{Frame, TextureAtlas};
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(io::Error),
+    /// The descriptor did not have the expected shape.
+    Format(String)
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FontError::Io(ref err) => write!(f, "IO error: {}", err),
+            FontError::Format(ref msg) => write!(f, "Invalid font: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for FontError {
+    fn from(err: io::Error) -> FontError {
+        FontError::Io(err)
+    }
+}
+
+/// A single character of a BitmapFont.
+///
+/// Offsets and advance are in pixels, with `y_offset`
+/// measured down from the top of the line as in BMFont.
+#[derive(Copy, Clone)]
+pub struct Glyph {
+    pub frame: Frame,
+    pub x_offset: f32,
+    pub y_offset: f32,
+    pub x_advance: f32
+}
+
+/// A font whose glyphs are frames of a TextureAtlas, for
+/// text that matches pixel art rather than smooth TTF text.
+///
+/// Text is laid out into quads in the same vertex format
+/// as the Scene so that it can be drawn with `Scene::draw_quads`.
+pub struct BitmapFont {
+    pub atlas: Rc<TextureAtlas>,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+    /// Distance between lines in pixels.
+    pub line_height: f32
+}
+
+/// Split a line of a BMFont descriptor into its tag and
+/// `key=value` pairs.
+///
+/// This handles both the text format (`char id=65 x=0`) and
+/// the XML format (`<char id="65" x="0"/>`), as they only
+/// differ in punctuation.
+fn parse_line(line: &str) -> Option<(String, HashMap<String, String>)> {
+    let line = line.trim()
+        .trim_left_matches('<')
+        .trim_right_matches('>')
+        .trim_right_matches('/');
+
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(token.clone());
+                    token.clear();
+                }
+            },
+            c => token.push(c)
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    let mut tokens = tokens.into_iter();
+    let tag = match tokens.next() {
+        Some(tag) => tag,
+        None => return None
+    };
+    let mut values = HashMap::new();
+    for token in tokens {
+        let mut parts = token.splitn(2, '=');
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            values.insert(key.to_string(), value.to_string());
+        }
+    }
+    Some((tag, values))
+}
+
+fn get_number(values: &HashMap<String, String>, key: &str) -> Result<f32, FontError> {
+    values.get(key)
+        .and_then(|x| x.parse::<f32>().ok())
+        .ok_or(FontError::Format(format!("missing or invalid `{}`", key)))
+}
+
+fn to_char(codepoint: f32) -> Result<char, FontError> {
+    ::std::char::from_u32(codepoint as u32)
+        .ok_or(FontError::Format(format!("`{}` is not a valid character", codepoint)))
+}
+
+/// Reject anything on a page other than the first, as only
+/// single page fonts are supported.
+fn check_page(values: &HashMap<String, String>, key: &str) -> Result<(), FontError> {
+    if values.contains_key(key) {
+        let page = try!(get_number(values, key));
+        if page != 0.0 {
+            return Err(FontError::Format(
+                format!("page {} found, only single page fonts are supported", page)));
+        }
+    }
+    Ok(())
+}
+
+/// A character of a BMFont descriptor, with its rectangle in
+/// pixels of the font's page.
+struct CharInfo {
+    c: char,
+    rect: [f32; 4],
+    x_offset: f32,
+    y_offset: f32,
+    x_advance: f32
+}
+
+/// The parts of a BMFont descriptor that `BitmapFont` uses.
+struct Descriptor {
+    line_height: f32,
+    /// The image file of the font's page.
+    page_file: Option<String>,
+    chars: Vec<CharInfo>,
+    kerning: HashMap<(char, char), f32>
+}
+
+fn parse_descriptor(descriptor: &str) -> Result<Descriptor, FontError> {
+    let mut line_height = None;
+    let mut page_file = None;
+    let mut chars = Vec::new();
+    let mut kerning = HashMap::new();
+
+    for line in descriptor.lines() {
+        let (tag, values) = match parse_line(line) {
+            Some(x) => x,
+            None => continue
+        };
+        match &tag[..] {
+            "common" => line_height = Some(try!(get_number(&values, "lineHeight"))),
+            "page" => {
+                try!(check_page(&values, "id"));
+                page_file = values.get("file").cloned();
+            },
+            "char" => {
+                try!(check_page(&values, "page"));
+                chars.push(CharInfo {
+                    c: try!(to_char(try!(get_number(&values, "id")))),
+                    rect: [
+                        try!(get_number(&values, "x")),
+                        try!(get_number(&values, "y")),
+                        try!(get_number(&values, "width")),
+                        try!(get_number(&values, "height"))],
+                    x_offset: try!(get_number(&values, "xoffset")),
+                    y_offset: try!(get_number(&values, "yoffset")),
+                    x_advance: try!(get_number(&values, "xadvance"))
+                });
+            },
+            "kerning" => {
+                let first = try!(to_char(try!(get_number(&values, "first"))));
+                let second = try!(to_char(try!(get_number(&values, "second"))));
+                kerning.insert((first, second), try!(get_number(&values, "amount")));
+            },
+            _ => {}
+        }
+    }
+
+    let line_height = try!(line_height
+        .ok_or(FontError::Format("missing `common` line".into())));
+    Ok(Descriptor {
+        line_height: line_height,
+        page_file: page_file,
+        chars: chars,
+        kerning: kerning
+    })
+}
+
+/// Glyphs for the frames named by codepoint with the given
+/// prefix, sitting on the bottom of a line as tall as the
+/// tallest of them, and that line height.
+fn frame_glyphs<'a, I>(frames: I, prefix: &str) -> (HashMap<char, Glyph>, f32)
+    where I: Iterator<Item=(&'a String, &'a Frame)> {
+    let mut glyphs = HashMap::new();
+    let mut line_height: f32 = 0.0;
+    for (name, frame) in frames {
+        if !name.starts_with(prefix) {
+            continue;
+        }
+        let c = name[prefix.len()..].parse::<u32>().ok()
+            .and_then(::std::char::from_u32);
+        if let Some(c) = c {
+            glyphs.insert(c, Glyph {
+                frame: *frame,
+                x_offset: frame.x_offset,
+                y_offset: frame.y_offset,
+                x_advance: frame.source_w
+            });
+            line_height = line_height.max(frame.source_h);
+        }
+    }
+    // glyphs sit on the bottom of the line, trimmed glyphs
+    // keep their offset within their untrimmed image
+    for glyph in glyphs.values_mut() {
+        glyph.y_offset += line_height - glyph.frame.source_h;
+    }
+    (glyphs, line_height)
+}
+
+impl BitmapFont {
+    /// Load a font from a BMFont descriptor in either its
+    /// text or XML format.
+    ///
+    /// The page image is expected to have been packed into
+    /// `atlas` as a frame named after its file stem. If there
+    /// is no such frame the glyph coordinates are taken to be
+    /// relative to the whole atlas texture, so a font exported
+    /// straight from BMFont can be used as an atlas of its own.
+    /// Only single page fonts are supported, anything on
+    /// another page is an error, and the page must have been
+    /// packed without rotating or trimming it.
+    pub fn from_bmfont<P>(atlas: Rc<TextureAtlas>, path: P) -> Result<BitmapFont, FontError>
+        where P: AsRef<Path> {
+        let mut file = try!(File::open(path));
+        let mut descriptor = String::new();
+        try!(file.read_to_string(&mut descriptor));
+        let descriptor = try!(parse_descriptor(&descriptor));
+
+        // the atlas page holding the font's page, and the pixel
+        // offset of the font's page within it
+        let mut texture_page = 0;
+        let mut page = (0.0, 0.0);
+        let stem = descriptor.page_file.as_ref()
+            .and_then(|x| Path::new(x).file_stem())
+            .and_then(|x| x.to_str());
+        if let Some((name, frame)) = stem.and_then(|x| atlas.get_frame(x).map(|f| (x, f))) {
+            // glyph rectangles are relative to the untouched
+            // page image, so they can't be mapped into a
+            // rotated or trimmed frame
+            if frame.rotated {
+                return Err(FontError::Format(
+                    format!("page `{}` is rotated in the atlas", name)));
+            }
+            if frame.x_offset != 0.0 || frame.y_offset != 0.0
+                || frame.source_w != frame.w || frame.source_h != frame.h {
+                return Err(FontError::Format(
+                    format!("page `{}` is trimmed in the atlas", name)));
+            }
+            let texture = atlas.texture(frame.page);
+            texture_page = frame.page;
+            // undo the half texel inset of the frame
+            page = (
+                frame.u1 * texture.get_width() as f32 - 0.5,
+                frame.v1 * texture.get_height().unwrap() as f32 - 0.5);
+        }
+
+        let (width, height) = {
+            let texture = atlas.texture(texture_page);
+            (texture.get_width() as f32, texture.get_height().unwrap() as f32)
+        };
+        let mut glyphs = HashMap::new();
+        for info in descriptor.chars.iter() {
+            let r = info.rect;
+            let mut frame = Frame::from_pixels(page.0 + r[0], page.1 + r[1], r[2], r[3], width, height);
+            frame.page = texture_page;
+            glyphs.insert(info.c, Glyph {
+                frame: frame,
+                x_offset: info.x_offset,
+                y_offset: info.y_offset,
+                x_advance: info.x_advance
+            });
+        }
+
+        Ok(BitmapFont {
+            atlas: atlas,
+            glyphs: glyphs,
+            kerning: descriptor.kerning,
+            line_height: descriptor.line_height
+        })
+    }
+
+    /// Build a font from atlas frames named by codepoint with
+    /// the given prefix, e.g. `glyph_65` for `A` with a prefix
+    /// of `glyph_`.
+    ///
+    /// Each glyph advances by its untrimmed width and the
+    /// line height is that of the tallest glyph.
+    pub fn from_frames(atlas: Rc<TextureAtlas>, prefix: &str) -> BitmapFont {
+        let (glyphs, line_height) = frame_glyphs(atlas.frames(), prefix);
+        BitmapFont {
+            atlas: atlas,
+            glyphs: glyphs,
+            kerning: HashMap::new(),
+            line_height: line_height
+        }
+    }
+
+    #[inline]
+    pub fn get_glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c)
+    }
+
+    /// Extra spacing in pixels between a pair of characters.
+    #[inline]
+    pub fn get_kerning(&self, first: char, second: char) -> f32 {
+        self.kerning.get(&(first, second)).cloned().unwrap_or(0.0)
+    }
+
+    /// The width in pixels of the widest line of `text`.
+    pub fn measure(&self, text: &str) -> f32 {
+        let mut widest: f32 = 0.0;
+        for line in text.lines() {
+            let mut width = 0.0;
+            let mut previous = None;
+            for c in line.chars() {
+                if let Some(p) = previous {
+                    width += self.get_kerning(p, c);
+                }
+                if let Some(glyph) = self.glyphs.get(&c) {
+                    width += glyph.x_advance;
+                }
+                previous = Some(c);
+            }
+            widest = widest.max(width);
+        }
+        widest
+    }
+
+    /// Lay out text as a quad per glyph, with `position` at
+    /// the top left of the first line and pixel sizes
+    /// multiplied by `scale`.
+    ///
+    /// Quads are grouped by the atlas page of their glyphs, in
+    /// order of page, so that each group can be passed to
+    /// `Scene::draw_quads` with its page. Characters missing
+    /// from the font are skipped.
+    pub fn layout(&self, text: &str, position: &Pnt3<f32>, scale: f32, color: [f32; 4]) -> Vec<(usize, Vec<Vertex>)> {
+        let mut pages: BTreeMap<usize, Vec<Vertex>> = BTreeMap::new();
+        let mut y = position.y;
+        for line in text.lines() {
+            let mut x = position.x;
+            let mut previous = None;
+            for c in line.chars() {
+                if let Some(p) = previous {
+                    x += self.get_kerning(p, c) * scale;
+                }
+                previous = Some(c);
+
+                let glyph = match self.glyphs.get(&c) {
+                    Some(glyph) => glyph,
+                    None => continue
+                };
+                let frame = &glyph.frame;
+                let x1 = x + glyph.x_offset * scale;
+                let x2 = x1 + frame.w * scale;
+                let y2 = y - glyph.y_offset * scale;
+                let y1 = y2 - frame.h * scale;
+                let z = position.z;
+                let uvs = frame.texcoords();
+                let vertices = pages.entry(frame.page).or_insert_with(Vec::new);
+                vertices.push(Vertex { position: [x1, y1, z], texcoords: uvs[0], color: color });
+                vertices.push(Vertex { position: [x1, y2, z], texcoords: uvs[1], color: color });
+                vertices.push(Vertex { position: [x2, y2, z], texcoords: uvs[2], color: color });
+                vertices.push(Vertex { position: [x2, y1, z], texcoords: uvs[3], color: color });
+
+                x += glyph.x_advance * scale;
+            }
+            y -= self.line_height * scale;
+        }
+        pages.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use textureatlas::Frame;
+    use super::{frame_glyphs, parse_descriptor, parse_line, FontError};
+
+    const TEXT: &'static str = "\
+info face=\"Pixel Sans\" size=8
+common lineHeight=10 base=8 scaleW=64 scaleH=64 pages=1
+page id=0 file=\"pixel sans.png\"
+chars count=2
+char id=65 x=1 y=2 width=5 height=6 xoffset=0 yoffset=2 xadvance=6 page=0
+char id=86 x=7 y=2 width=5 height=6 xoffset=1 yoffset=2 xadvance=6 page=0
+kernings count=1
+kerning first=65 second=86 amount=-1
+";
+
+    fn is_format_error<T>(result: Result<T, FontError>) -> bool {
+        match result {
+            Err(FontError::Format(_)) => true,
+            _ => false
+        }
+    }
+
+    #[test]
+    fn parses_text_and_xml_lines() {
+        let (tag, values) = parse_line("char id=65 x=1  width=5").unwrap();
+        assert_eq!(tag, "char");
+        assert_eq!(values["id"], "65");
+        assert_eq!(values["x"], "1");
+        assert_eq!(values["width"], "5");
+
+        let (tag, values) = parse_line("  <char id=\"65\" x=\"1\" width=\"5\"/>").unwrap();
+        assert_eq!(tag, "char");
+        assert_eq!(values["id"], "65");
+        assert_eq!(values["width"], "5");
+
+        assert!(parse_line("   ").is_none());
+    }
+
+    #[test]
+    fn quoted_values_keep_their_spaces() {
+        let (tag, values) = parse_line("info face=\"Pixel Sans\" size=8").unwrap();
+        assert_eq!(tag, "info");
+        assert_eq!(values["face"], "Pixel Sans");
+        assert_eq!(values["size"], "8");
+
+        let (_, values) = parse_line("<page id=\"0\" file=\"my font.png\" />").unwrap();
+        assert_eq!(values["file"], "my font.png");
+    }
+
+    #[test]
+    fn parses_chars_and_kerning() {
+        let descriptor = parse_descriptor(TEXT).unwrap();
+        assert_eq!(descriptor.line_height, 10.0);
+        assert_eq!(descriptor.page_file, Some("pixel sans.png".to_string()));
+        assert_eq!(descriptor.chars.len(), 2);
+        let a = &descriptor.chars[0];
+        assert_eq!(a.c, 'A');
+        assert_eq!(a.rect, [1.0, 2.0, 5.0, 6.0]);
+        assert_eq!((a.x_offset, a.y_offset, a.x_advance), (0.0, 2.0, 6.0));
+        assert_eq!(descriptor.kerning.get(&('A', 'V')), Some(&-1.0));
+        assert_eq!(descriptor.kerning.get(&('V', 'A')), None);
+    }
+
+    #[test]
+    fn rejects_a_missing_common_line() {
+        let text = TEXT.replace("common lineHeight=10 base=8 scaleW=64 scaleH=64 pages=1\n", "");
+        assert!(is_format_error(parse_descriptor(&text)));
+    }
+
+    #[test]
+    fn rejects_other_pages() {
+        let text = TEXT.replace("page id=0", "page id=1");
+        assert!(is_format_error(parse_descriptor(&text)));
+        let text = TEXT.replace("xadvance=6 page=0\nkernings", "xadvance=6 page=1\nkernings");
+        assert!(is_format_error(parse_descriptor(&text)));
+    }
+
+    #[test]
+    fn frame_glyphs_sit_on_the_bottom_of_the_line() {
+        let mut frames = HashMap::new();
+        frames.insert("glyph_65".to_string(), Frame::from_pixels(0.0, 0.0, 4.0, 8.0, 64.0, 64.0));
+        // a 5x6 glyph trimmed to 3x3, 1 pixel in and 2 down
+        let mut trimmed = Frame::from_pixels(8.0, 0.0, 3.0, 3.0, 64.0, 64.0);
+        trimmed.x_offset = 1.0;
+        trimmed.y_offset = 2.0;
+        trimmed.source_w = 5.0;
+        trimmed.source_h = 6.0;
+        frames.insert("glyph_46".to_string(), trimmed);
+        frames.insert("icon".to_string(), Frame::from_pixels(0.0, 8.0, 16.0, 16.0, 64.0, 64.0));
+
+        let (glyphs, line_height) = frame_glyphs(frames.iter(), "glyph_");
+        assert_eq!(line_height, 8.0);
+        assert_eq!(glyphs.len(), 2);
+        let a = &glyphs[&'A'];
+        assert_eq!((a.x_offset, a.y_offset, a.x_advance), (0.0, 0.0, 4.0));
+        let dot = &glyphs[&'.'];
+        assert_eq!((dot.x_offset, dot.y_offset, dot.x_advance), (1.0, 4.0, 5.0));
+    }
+}
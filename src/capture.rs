@@ -0,0 +1,250 @@
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::Instant;
+
+use gif;
+use gif::SetParameter;
+use glium::backend::Facade;
+use glium::texture::RawImage2d;
+
+use headless;
+use headless::RgbaImage;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// Writing a file failed, which is also how the GIF
+    /// encoder reports its errors.
+    Io(io::Error)
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CaptureError::Io(ref err) => write!(f, "IO error: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(err: io::Error) -> CaptureError {
+        CaptureError::Io(err)
+    }
+}
+
+/// Read the most recently finished frame back from the window.
+///
+/// This must be called after `Frame::finish` so that the
+/// frame has been swapped to the front buffer.
+pub fn read_frame<F: Facade>(display: &F) -> RgbaImage {
+    let raw: RawImage2d<u8> = display.get_context().read_front_buffer();
+    let mut image = headless::from_raw(raw);
+    // the window is cleared to transparent black, which
+    // should come out black rather than see-through
+    for pixel in image.pixels_mut() {
+        pixel.data[3] = 255;
+    }
+    image
+}
+
+fn write_gif_frame<W: io::Write>(encoder: &mut gif::Encoder<W>, image: RgbaImage, delay: f32) -> Result<(), CaptureError> {
+    let (width, height) = image.dimensions();
+    let mut pixels = image.into_raw();
+    let mut frame = gif::Frame::from_rgba(width as u16, height as u16, &mut pixels);
+    // GIF delays are in hundredths of a second
+    frame.delay = (delay * 100.0).round().max(1.0) as u16;
+    try!(encoder.write_frame(&frame));
+    Ok(())
+}
+
+fn seconds_between(earlier: Instant, later: Instant) -> f32 {
+    let elapsed = later.duration_since(earlier);
+    elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9
+}
+
+/// Encode frames into a looping GIF as they are captured,
+/// until the sender is dropped.
+///
+/// Each frame is shown until the next one was captured, and
+/// the last for as long as the one before it.
+fn encode_gif(path: &Path, first: (RgbaImage, Instant), frames: Receiver<(RgbaImage, Instant)>) -> Result<(), CaptureError> {
+    let (mut pending, mut time) = first;
+    let (width, height) = pending.dimensions();
+    let mut file = try!(File::create(path));
+    let mut encoder = try!(gif::Encoder::new(&mut file, width as u16, height as u16, &[]));
+    try!(encoder.set(gif::Repeat::Infinite));
+    let mut delay = 1.0 / 60.0;
+    for (image, next) in frames.iter() {
+        delay = seconds_between(time, next);
+        try!(write_gif_frame(&mut encoder, pending, delay));
+        pending = image;
+        time = next;
+    }
+    write_gif_frame(&mut encoder, pending, delay)
+}
+
+struct GifRecording {
+    /// Frames go to the thread encoding them once the first
+    /// has been captured.
+    frames: Option<Sender<(RgbaImage, Instant)>>,
+    remaining: usize
+}
+
+/// Captures screenshots and GIFs from the running game.
+///
+/// Requests are made at any point during a frame and then
+/// serviced by `process` once the frame has been presented.
+/// Output is written to `dir` with a numbered file name.
+///
+/// GIFs are encoded on a thread of their own as the frames
+/// are captured, so that it doesn't hold up rendering.
+pub struct Capture {
+    dir: PathBuf,
+    screenshot: bool,
+    recording: Option<GifRecording>,
+    /// Number of GIFs still being encoded, which report
+    /// their path or error on `encoded` when done.
+    encoding: usize,
+    encoded: (Sender<Result<PathBuf, CaptureError>>, Receiver<Result<PathBuf, CaptureError>>),
+    count: usize
+}
+
+impl Capture {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Capture {
+        Capture {
+            dir: dir.as_ref().to_path_buf(),
+            screenshot: false,
+            recording: None,
+            encoding: 0,
+            encoded: mpsc::channel(),
+            count: 0
+        }
+    }
+
+    /// Save the next presented frame as a PNG.
+    #[inline]
+    pub fn request_screenshot(&mut self) {
+        self.screenshot = true;
+    }
+
+    /// Capture the next `frames` presented frames into a GIF.
+    ///
+    /// Asking for no frames doesn't start a recording.
+    pub fn start_recording(&mut self, frames: usize) {
+        if frames == 0 {
+            return;
+        }
+        self.recording = Some(GifRecording {
+            frames: None,
+            remaining: frames
+        });
+    }
+
+    /// Stop recording early, saving whatever has been captured.
+    ///
+    /// The GIF is finished in the background and its path is
+    /// returned by a later `process` or by `finish`.
+    #[inline]
+    pub fn stop_recording(&mut self) {
+        self.recording = None;
+    }
+
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Service any outstanding requests, returning the paths
+    /// of any files that were written.
+    pub fn process<F: Facade>(&mut self, display: &F) -> Result<Vec<PathBuf>, CaptureError> {
+        let mut written = Vec::new();
+        while let Ok(result) = self.encoded.1.try_recv() {
+            self.encoding -= 1;
+            written.push(try!(result));
+        }
+        if !self.screenshot && self.recording.is_none() {
+            return Ok(written);
+        }
+
+        let image = read_frame(display);
+
+        if self.screenshot {
+            self.screenshot = false;
+            let path = try!(self.next_path("screenshot", "png"));
+            try!(image.save(&path));
+            written.push(path);
+        }
+
+        if self.recording.is_some() {
+            try!(self.record(image));
+        }
+
+        Ok(written)
+    }
+
+    /// Stop any recording and wait for every GIF to be
+    /// written, returning their paths.
+    pub fn finish(&mut self) -> Result<Vec<PathBuf>, CaptureError> {
+        self.stop_recording();
+        let mut written = Vec::new();
+        while self.encoding > 0 {
+            self.encoding -= 1;
+            match self.encoded.1.recv() {
+                Ok(result) => written.push(try!(result)),
+                Err(_) => break
+            }
+        }
+        Ok(written)
+    }
+
+    /// Pass a frame to the thread encoding the recording,
+    /// starting it on the first frame.
+    fn record(&mut self, image: RgbaImage) -> Result<(), CaptureError> {
+        let frame = (image, Instant::now());
+        match self.recording.as_ref().and_then(|x| x.frames.clone()) {
+            // a failed encoder reports its error on `encoded`
+            Some(frames) => { let _ = frames.send(frame); },
+            None => {
+                let path = try!(self.next_path("recording", "gif"));
+                let (sender, receiver) = mpsc::channel();
+                let encoded = self.encoded.0.clone();
+                thread::spawn(move || {
+                    let result = encode_gif(&path, frame, receiver).map(|_| path);
+                    let _ = encoded.send(result);
+                });
+                self.encoding += 1;
+                if let Some(ref mut recording) = self.recording {
+                    recording.frames = Some(sender);
+                }
+            }
+        }
+
+        let finished = match self.recording {
+            Some(ref mut recording) => {
+                recording.remaining -= 1;
+                recording.remaining == 0
+            },
+            None => false
+        };
+        if finished {
+            self.stop_recording();
+        }
+        Ok(())
+    }
+
+    fn next_path(&mut self, prefix: &str, extension: &str) -> io::Result<PathBuf> {
+        try!(fs::create_dir_all(&self.dir));
+        loop {
+            let path = self.dir.join(format!("{}_{:04}.{}", prefix, self.count, extension));
+            self.count += 1;
+            if !path.exists() {
+                return Ok(path);
+            }
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use glium::{Blend, DrawParameters, Program, Surface, VertexBuffer};
+use glium::backend::Facade;
+use glium::index::{NoIndices, PrimitiveType};
+use na::{Mat4, Pnt2};
+
+use scene::Scene;
+use text;
+use text::{Font, TextRenderer, TextStyle};
+use tilemap::{Tile, TileMap};
+
+/// Frame time in seconds that fills the height of the graph.
+const GRAPH_MAX: f32 = 1.0 / 20.0;
+const GRAPH_HEIGHT: f32 = 60.0;
+const GRAPH_BAR_WIDTH: f32 = 2.0;
+const MARGIN: f32 = 8.0;
+const TEXT_SIZE: f32 = 14.0;
+/// Size in pixels of each dot of the built-in font, which
+/// puts its lines TEXT_SIZE apart.
+const DOT_SIZE: f32 = 2.0;
+/// Number of sides used to approximate a circle.
+const CIRCLE_SEGMENTS: usize = 24;
+
+/// The value of a single named statistic.
+#[derive(Clone, Debug)]
+pub enum Stat {
+    Count(u64),
+    Text(String)
+}
+
+impl fmt::Display for Stat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stat::Count(n) => write!(f, "{}", n),
+            Stat::Text(ref text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Performance statistics gathered over a frame.
+///
+/// Frame times are kept for a rolling window of frames
+/// while everything else is reset at the start of each
+/// frame. Anything can add its own entries with `count`
+/// or `set`, which are shown in the order first added.
+pub struct Stats {
+    history: usize,
+    frame_times: VecDeque<f32>,
+    entries: Vec<(String, Stat)>
+}
+
+impl Stats {
+    /// Create Stats that remember the last `history` frame times.
+    pub fn new(history: usize) -> Stats {
+        Stats {
+            history: history,
+            frame_times: VecDeque::with_capacity(history),
+            entries: Vec::new()
+        }
+    }
+
+    /// Start a new frame, recording how long the last one took.
+    pub fn begin_frame(&mut self, frame_time: f32) {
+        if self.frame_times.len() == self.history {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+        self.entries.clear();
+    }
+
+    fn entry(&mut self, name: &str) -> &mut Stat {
+        let index = match self.entries.iter().position(|x| x.0 == name) {
+            Some(index) => index,
+            None => {
+                self.entries.push((name.to_string(), Stat::Count(0)));
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[index].1
+    }
+
+    /// Add `n` to a counter, starting it at zero if needed.
+    pub fn count(&mut self, name: &str, n: u64) {
+        let entry = self.entry(name);
+        *entry = match *entry {
+            Stat::Count(x) => Stat::Count(x + n),
+            Stat::Text(_) => Stat::Count(n),
+        };
+    }
+
+    /// Set an entry to anything that can be displayed.
+    pub fn set<T: fmt::Display>(&mut self, name: &str, value: T) {
+        *self.entry(name) = Stat::Text(value.to_string());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Stat> {
+        self.entries.iter().find(|x| x.0 == name).map(|x| &x.1)
+    }
+
+    #[inline]
+    pub fn entries(&self) -> &[(String, Stat)] {
+        &self.entries
+    }
+
+    #[inline]
+    pub fn frame_times(&self) -> &VecDeque<f32> {
+        &self.frame_times
+    }
+
+    /// Frames per second averaged over the history.
+    pub fn fps(&self) -> f32 {
+        let total: f32 = self.frame_times.iter().fold(0.0, |acc, x| acc + x);
+        if total > 0.0 {
+            self.frame_times.len() as f32 / total
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Vertex {
+    position: [f32; 2],
+    color: [f32; 4]
+}
+
+implement_vertex!(Vertex, position, color);
+
+// TODO: propagate error
+fn get_program<F>(display: &F) -> Program
+    where F: Facade {
+    program!(display,
+        140 => {
+            vertex: "
+                #version 140
+                uniform mat4 matrix;
+                in vec2 position;
+                in vec4 color;
+                out vec4 v_color;
+                void main() {
+                    gl_Position = matrix * vec4(position, 0.0, 1.0);
+                    v_color = color;
+                }
+            ",
+
+            fragment: "
+                #version 140
+                in vec4 v_color;
+                out vec4 f_color;
+                void main() {
+                    f_color = v_color;
+                }
+            "
+        },
+    ).unwrap()
+}
+
+/// Immediate mode drawing of flat coloured shapes, for
+/// seeing what collision and AI code is doing.
+///
+/// Shapes are submitted over a frame in world units and
+/// drawn together in one batch by `draw`, which also
+/// clears them ready for the next frame. Outlines are
+/// `line_width` units thick.
+pub struct DebugDraw<F> {
+    pub line_width: f32,
+    vertices: Vec<Vertex>,
+    program: Program,
+    display: F
+}
+
+impl<F: Facade + Clone> DebugDraw<F> {
+    pub fn new(display: &F) -> DebugDraw<F> {
+        DebugDraw {
+            line_width: 1.0,
+            vertices: Vec::new(),
+            program: get_program(display),
+            display: display.clone()
+        }
+    }
+
+    pub fn triangle(&mut self, a: &Pnt2<f32>, b: &Pnt2<f32>, c: &Pnt2<f32>, color: [f32; 4]) {
+        self.vertices.push(Vertex { position: [a.x, a.y], color: color });
+        self.vertices.push(Vertex { position: [b.x, b.y], color: color });
+        self.vertices.push(Vertex { position: [c.x, c.y], color: color });
+    }
+
+    pub fn line(&mut self, a: &Pnt2<f32>, b: &Pnt2<f32>, color: [f32; 4]) {
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            return;
+        }
+        // half the width along the normal on either side
+        let nx = -dy / length * self.line_width / 2.0;
+        let ny = dx / length * self.line_width / 2.0;
+        let corners = [
+            Pnt2::new(a.x + nx, a.y + ny),
+            Pnt2::new(b.x + nx, b.y + ny),
+            Pnt2::new(b.x - nx, b.y - ny),
+            Pnt2::new(a.x - nx, a.y - ny),
+        ];
+        self.fill_polygon(&corners, color);
+    }
+
+    /// Draw a line with a head at `to`.
+    pub fn arrow(&mut self, from: &Pnt2<f32>, to: &Pnt2<f32>, color: [f32; 4]) {
+        let (dx, dy) = (to.x - from.x, to.y - from.y);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            return;
+        }
+        let (dx, dy) = (dx / length, dy / length);
+        let size = (self.line_width * 4.0).min(length);
+        let base = Pnt2::new(to.x - dx * size, to.y - dy * size);
+        self.line(from, &base, color);
+        let left = Pnt2::new(base.x - dy * size / 2.0, base.y + dx * size / 2.0);
+        let right = Pnt2::new(base.x + dy * size / 2.0, base.y - dx * size / 2.0);
+        self.triangle(&left, to, &right, color);
+    }
+
+    /// Draw the outline of a closed polygon.
+    pub fn polygon(&mut self, points: &[Pnt2<f32>], color: [f32; 4]) {
+        for (i, a) in points.iter().enumerate() {
+            let b = &points[(i + 1) % points.len()];
+            self.line(a, b, color);
+        }
+    }
+
+    /// Fill a convex polygon.
+    pub fn fill_polygon(&mut self, points: &[Pnt2<f32>], color: [f32; 4]) {
+        for i in 2..points.len() {
+            self.triangle(&points[0], &points[i - 1], &points[i], color);
+        }
+    }
+
+    pub fn rect(&mut self, min: &Pnt2<f32>, max: &Pnt2<f32>, color: [f32; 4]) {
+        self.polygon(&rect_corners(min, max), color);
+    }
+
+    pub fn fill_rect(&mut self, min: &Pnt2<f32>, max: &Pnt2<f32>, color: [f32; 4]) {
+        self.fill_polygon(&rect_corners(min, max), color);
+    }
+
+    pub fn circle(&mut self, center: &Pnt2<f32>, radius: f32, color: [f32; 4]) {
+        self.polygon(&circle_points(center, radius), color);
+    }
+
+    pub fn fill_circle(&mut self, center: &Pnt2<f32>, radius: f32, color: [f32; 4]) {
+        self.fill_polygon(&circle_points(center, radius), color);
+    }
+
+    /// Draw lines between the tiles of a TileMap.
+    pub fn tile_grid<T>(&mut self, map: &TileMap<T>, color: [f32; 4])
+        where T: Default + Tile {
+        let width = map.width as f32 * map.tile_width as f32;
+        let height = map.height as f32 * map.tile_height as f32;
+        for x in 0..map.width + 1 {
+            let x = x as f32 * map.tile_width as f32;
+            self.line(&Pnt2::new(x, 0.0), &Pnt2::new(x, height), color);
+        }
+        for y in 0..map.height + 1 {
+            let y = y as f32 * map.tile_height as f32;
+            self.line(&Pnt2::new(0.0, y), &Pnt2::new(width, y), color);
+        }
+    }
+
+    /// Outline the quad of every sprite in a Scene.
+    pub fn sprite_bounds<G>(&mut self, scene: &Scene<G>, color: [f32; 4])
+        where G: Facade + Clone {
+        for corners in scene.sprite_bounds() {
+            self.polygon(&corners, color);
+        }
+    }
+
+    /// Add text in a tiny built-in font, for when there is no
+    /// other font to hand. Each glyph is 3x5 dots of `dot`
+    /// units, `top_left` is the top left of the first line and
+    /// lower case letters are drawn as upper case.
+    pub fn text(&mut self, top_left: &Pnt2<f32>, text: &str, dot: f32, color: [f32; 4]) {
+        for (row, line) in text.lines().enumerate() {
+            let top = top_left.y - row as f32 * 7.0 * dot;
+            for (column, c) in line.chars().enumerate() {
+                let left = top_left.x + column as f32 * 4.0 * dot;
+                let rows = match glyph(c.to_ascii_uppercase()) {
+                    Some(rows) => rows,
+                    None => continue
+                };
+                for (y, bits) in rows.iter().enumerate() {
+                    for x in 0..3 {
+                        if bits & (0b100 >> x) == 0 {
+                            continue;
+                        }
+                        let min = Pnt2::new(left + x as f32 * dot, top - (y + 1) as f32 * dot);
+                        self.fill_rect(&min, &Pnt2::new(min.x + dot, min.y + dot), color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw everything submitted since the last call on top
+    /// of whatever has already been drawn.
+    pub fn draw<S: Surface>(&mut self, surface: &mut S, viewproj: &Mat4<f32>) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        let vertex_buffer = VertexBuffer::new(&self.display, &self.vertices)
+            .ok().expect("Could not create VertexBuffer");
+        let uniforms = uniform! {
+            matrix: viewproj.clone()
+        };
+        let mut params = DrawParameters::default();
+        params.blend = Blend::alpha_blending();
+        surface.draw(
+            &vertex_buffer,
+            &NoIndices(PrimitiveType::TrianglesList),
+            &self.program,
+            &uniforms,
+            &params).unwrap();
+        self.vertices.clear();
+    }
+}
+
+fn rect_corners(min: &Pnt2<f32>, max: &Pnt2<f32>) -> [Pnt2<f32>; 4] {
+    [
+        Pnt2::new(min.x, min.y),
+        Pnt2::new(min.x, max.y),
+        Pnt2::new(max.x, max.y),
+        Pnt2::new(max.x, min.y),
+    ]
+}
+
+/// The rows of a glyph in the built-in font from top to
+/// bottom, with the highest of the three bits on the left.
+fn glyph(c: char) -> Option<[u8; 5]> {
+    let rows = match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        _ => return None
+    };
+    Some(rows)
+}
+
+fn circle_points(center: &Pnt2<f32>, radius: f32) -> Vec<Pnt2<f32>> {
+    (0..CIRCLE_SEGMENTS).map(|i| {
+        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * ::std::f32::consts::PI;
+        Pnt2::new(center.x + radius * angle.cos(), center.y + radius * angle.sin())
+    }).collect()
+}
+
+/// Draws Stats over the top of the game in screen space.
+///
+/// Text is drawn with the given font, or with the built-in
+/// font of DebugDraw when there isn't one.
+pub struct DebugOverlay<F> {
+    pub visible: bool,
+    shapes: DebugDraw<F>,
+    text: TextRenderer,
+    font: Option<Font>
+}
+
+impl<F: Facade + Clone> DebugOverlay<F> {
+    pub fn new(display: &F, font: Option<Font>) -> DebugOverlay<F> {
+        DebugOverlay {
+            visible: false,
+            shapes: DebugDraw::new(display),
+            text: TextRenderer::new(display),
+            font: font
+        }
+    }
+
+    #[inline]
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn draw<S: Surface>(&mut self, surface: &mut S, stats: &Stats) {
+        if !self.visible {
+            return;
+        }
+
+        let (width, height) = surface.get_dimensions();
+        let (width, height) = (width as f32, height as f32);
+        let projection = text::screen_projection(width, height);
+
+        let mut lines = vec![format!("FPS: {:.1}", stats.fps())];
+        if let Some(frame_time) = stats.frame_times().back() {
+            lines.push(format!("Frame: {:.2} ms", frame_time * 1000.0));
+        }
+        for &(ref name, ref value) in stats.entries() {
+            lines.push(format!("{}: {}", name, value));
+        }
+        let lines = lines.join("\n");
+
+        self.graph(stats, width);
+        if self.font.is_none() {
+            self.shapes.text(&Pnt2::new(MARGIN, height - MARGIN), &lines, DOT_SIZE, [1.0, 1.0, 1.0, 1.0]);
+        }
+        self.shapes.draw(surface, &projection);
+
+        if let Some(ref font) = self.font {
+            let style = TextStyle { size: TEXT_SIZE, .. TextStyle::default() };
+            let top = height - MARGIN - TEXT_SIZE;
+            self.text.draw_screen(surface, font, &lines, MARGIN, top, &style);
+        }
+    }
+
+    /// Add a bar per frame along the bottom right of the
+    /// screen, turning red for frames slower than 60fps.
+    fn graph(&mut self, stats: &Stats, width: f32) {
+        let times = stats.frame_times();
+        if times.is_empty() {
+            return;
+        }
+
+        let left = width - MARGIN - times.len() as f32 * GRAPH_BAR_WIDTH;
+        let right = width - MARGIN;
+        let bottom = MARGIN;
+
+        // background and a line marking 60fps
+        self.shapes.fill_rect(
+            &Pnt2::new(left, bottom),
+            &Pnt2::new(right, bottom + GRAPH_HEIGHT),
+            [0.0, 0.0, 0.0, 0.5]);
+        let target = bottom + GRAPH_HEIGHT * (1.0 / 60.0) / GRAPH_MAX;
+        self.shapes.fill_rect(
+            &Pnt2::new(left, target),
+            &Pnt2::new(right, target + 1.0),
+            [1.0, 1.0, 1.0, 0.5]);
+
+        for (i, &time) in times.iter().enumerate() {
+            let x = left + i as f32 * GRAPH_BAR_WIDTH;
+            let h = GRAPH_HEIGHT * (time / GRAPH_MAX).min(1.0);
+            let color = if time > 1.0 / 60.0 { [1.0, 0.2, 0.2, 0.8] } else { [0.2, 1.0, 0.2, 0.8] };
+            self.shapes.fill_rect(
+                &Pnt2::new(x, bottom),
+                &Pnt2::new(x + GRAPH_BAR_WIDTH - 1.0, bottom + h),
+                color);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+/// FNV-1a, which unlike the standard library's hasher gives
+/// the same hash from one run and build to the next, so it
+/// can be stored in replays and the `pack` cache.
+///
+/// Integers are written little endian.
+pub struct Fnv(u64);
+
+impl Fnv {
+    pub fn new() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_u32(&mut self, x: u32) {
+        self.write(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]);
+    }
+
+    pub fn write_u64(&mut self, x: u64) {
+        self.write_u32(x as u32);
+        self.write_u32((x >> 32) as u32);
+    }
+
+    /// Strings are prefixed with their length so that
+    /// `["ab", "c"]` and `["a", "bc"]` differ.
+    pub fn write_str(&mut self, s: &str) {
+        self.write_u32(s.len() as u32);
+        self.write(s.as_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fnv;
+
+    #[test]
+    fn matches_reference_values() {
+        assert_eq!(Fnv::new().finish(), 0xcbf29ce484222325);
+        let mut hasher = Fnv::new();
+        hasher.write(b"foobar");
+        assert_eq!(hasher.finish(), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn writes_integers_little_endian() {
+        let mut bytes = Fnv::new();
+        bytes.write(b"abcdefgh");
+        let mut integer = Fnv::new();
+        integer.write_u64(0x6867666564636261);
+        assert_eq!(integer.finish(), bytes.finish());
+        assert_eq!(integer.finish(), 0x25da8c1836a8d66d);
+    }
+
+    #[test]
+    fn strings_are_length_prefixed() {
+        let (mut a, mut b) = (Fnv::new(), Fnv::new());
+        a.write_str("ab");
+        a.write_str("c");
+        b.write_str("a");
+        b.write_str("bc");
+        assert!(a.finish() != b.finish());
+    }
+}
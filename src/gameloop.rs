@@ -0,0 +1,185 @@
+use std::cell::Cell;
+use std::time::Instant;
+
+/// The longest frame, in seconds, that the GameLoop will
+/// try to catch up on before dropping simulation time.
+pub const DEFAULT_MAX_FRAME_TIME: f64 = 0.25;
+
+/// A source of time for the GameLoop.
+///
+/// This is abstracted so that the loop can be driven
+/// by something other than the wall clock, such as
+/// when running a simulation without a window.
+pub trait Clock {
+    /// The current time in seconds since some fixed point.
+    fn now(&self) -> f64;
+}
+
+/// A Clock backed by the system's monotonic timer.
+pub struct SystemClock {
+    start: Instant
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now()
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        let elapsed = self.start.elapsed();
+        elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9
+    }
+}
+
+/// A Clock that only moves forward when it is told to.
+pub struct ManualClock {
+    time: Cell<f64>
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            time: Cell::new(0.0)
+        }
+    }
+
+    /// Move the clock forward by `dt` seconds.
+    pub fn advance(&self, dt: f64) {
+        self.time.set(self.time.get() + dt);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> f64 {
+        self.time.get()
+    }
+}
+
+/// Runs the simulation at a fixed tick rate regardless
+/// of how quickly frames are being rendered.
+///
+/// Elapsed time is gathered into an accumulator which is
+/// then consumed in steps of exactly `dt`. Whatever is
+/// left over is returned as an interpolation factor so
+/// that rendering can blend between the previous and
+/// current simulation states.
+pub struct GameLoop<C> {
+    clock: C,
+    dt: f64,
+    max_frame_time: f64,
+    accumulator: f64,
+    previous: f64,
+    ticks: u64
+}
+
+impl<C: Clock> GameLoop<C> {
+    pub fn new(clock: C, dt: f64) -> GameLoop<C> {
+        GameLoop::with_max_frame_time(clock, dt, DEFAULT_MAX_FRAME_TIME)
+    }
+
+    /// Create a GameLoop that never tries to simulate more
+    /// than `max_frame_time` seconds in a single frame.
+    ///
+    /// Without this cap a slow frame causes more ticks to
+    /// be run on the next frame, which makes that frame
+    /// slower still, until the game grinds to a halt.
+    pub fn with_max_frame_time(clock: C, dt: f64, max_frame_time: f64) -> GameLoop<C> {
+        assert!(dt > 0.0, "GameLoop tick length must be positive");
+        let previous = clock.now();
+        GameLoop {
+            clock: clock,
+            dt: dt,
+            max_frame_time: max_frame_time,
+            accumulator: 0.0,
+            previous: previous,
+            ticks: 0
+        }
+    }
+
+    #[inline]
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// The length of a single simulation tick in seconds.
+    #[inline]
+    pub fn dt(&self) -> f64 {
+        self.dt
+    }
+
+    /// The total number of ticks simulated so far.
+    #[inline]
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Advance the loop to the clock's current time, calling
+    /// `update` once for every whole tick that has elapsed.
+    ///
+    /// Returns the interpolation factor in `[0, 1)` between
+    /// the last two simulated states for use when rendering.
+    pub fn step<U>(&mut self, mut update: U) -> f32
+        where U: FnMut(f64) {
+        let now = self.clock.now();
+        let mut frame_time = now - self.previous;
+        if frame_time > self.max_frame_time {
+            frame_time = self.max_frame_time;
+        }
+        self.previous = now;
+        self.accumulator += frame_time;
+
+        while self.accumulator >= self.dt {
+            update(self.dt);
+            self.accumulator -= self.dt;
+            self.ticks += 1;
+        }
+
+        (self.accumulator / self.dt) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_a_tick_for_each_whole_dt() {
+        let mut game_loop = GameLoop::with_max_frame_time(ManualClock::new(), 0.125, 1.0);
+        let mut ticks = 0;
+        game_loop.clock().advance(0.4375);
+        game_loop.step(|dt| {
+            assert_eq!(dt, 0.125);
+            ticks += 1;
+        });
+        assert_eq!(ticks, 3);
+        assert_eq!(game_loop.ticks(), 3);
+
+        // the leftover is carried into the next step
+        game_loop.clock().advance(0.0625);
+        game_loop.step(|_| ticks += 1);
+        assert_eq!(ticks, 4);
+    }
+
+    #[test]
+    fn caps_catch_up_ticks() {
+        let mut game_loop = GameLoop::with_max_frame_time(ManualClock::new(), 0.125, 0.5);
+        game_loop.clock().advance(10.0);
+        let mut ticks = 0;
+        let alpha = game_loop.step(|_| ticks += 1);
+        assert_eq!(ticks, 4);
+        assert_eq!(alpha, 0.0);
+    }
+
+    #[test]
+    fn returns_leftover_as_alpha() {
+        let mut game_loop = GameLoop::new(ManualClock::new(), 0.125);
+        game_loop.clock().advance(0.1875);
+        assert_eq!(game_loop.step(|_| {}), 0.5);
+        game_loop.clock().advance(0.03125);
+        assert_eq!(game_loop.step(|_| {}), 0.75);
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use glium::backend::Facade;
+use na;
+use na::{Iso3, Mat4, Ortho3, Pnt3, Vec3};
+use na::{ToHomogeneous};
+
+use headless::{Offscreen, RgbaImage, Tolerance};
+use headless;
+use scene::Scene;
+use textureatlas::TextureAtlas;
+use tilemap::TileMap;
+use OvergroundTile;
+
+/// A single rendering scenario with a reference image.
+struct Case<F> {
+    name: &'static str,
+    render: fn(&F) -> RgbaImage
+}
+
+/// A view projection where world units map one to one onto
+/// the pixels of a `width` by `height` target with the
+/// origin in the bottom left corner.
+fn pixel_viewproj(width: u32, height: u32) -> Mat4<f32> {
+    let (width, height) = (width as f32, height as f32);
+    let proj = Ortho3::new(width, height, -1.0, 1.0);
+    let mut view = Iso3::new(na::zero(), na::zero());
+    let (x, y) = (width / 2.0, height / 2.0);
+    view.look_at_z(&Pnt3::new(x, y, -1.0), &Pnt3::new(x, y, 0.0), &Vec3::y());
+    proj.to_mat() * na::inv(&view.to_homogeneous()).unwrap()
+}
+
+/// Alternating grass and dirt so that misplaced tiles
+/// or swapped frames show up.
+fn tilemap_checkerboard<F: Facade + Clone>(display: &F) -> RgbaImage {
+    let atlas = Rc::new(TextureAtlas::from_packed(
+        "resources/overground.png",
+        "resources/overground.json",
+        display));
+    let mut tiles = Vec::new();
+    for y in 0..4 {
+        for x in 0..4 {
+            if (x + y) % 2 == 0 {
+                tiles.push(OvergroundTile::Grass);
+            } else {
+                tiles.push(OvergroundTile::Dirt);
+            }
+        }
+    }
+    let tilemap = TileMap::new(display, 4, 4, 16, 16, tiles, atlas);
+
+    let target = Offscreen::new(display, 64, 64);
+    let viewproj = pixel_viewproj(target.width, target.height);
+    target.draw(display, |surface| tilemap.draw(surface, &viewproj));
+    target.read()
+}
+
+/// Overlapping sprites at different depths, which must be
+/// drawn in the same order no matter how they are stored.
+fn scene_depth_order<F: Facade + Clone>(display: &F) -> RgbaImage {
+    let atlas = Rc::new(TextureAtlas::from_packed(
+        "resources/spritesheet.png",
+        "resources/spritesheet.json",
+        display));
+    let mut scene = Scene::new(display, atlas);
+    scene.add_sprite("back", "player");
+    scene.add_sprite("front", "player");
+    // depths have to stay within the [-2, 0] range that
+    // `pixel_viewproj` can see or the sprite is clipped
+    scene.with_sprite_mut("back", |x| {
+        x.set_position(4.0, 4.0);
+        x.set_position_z(-0.5);
+    });
+    scene.with_sprite_mut("front", |x| {
+        x.set_position(12.0, 12.0);
+        x.set_position_z(0.0);
+    });
+
+    let target = Offscreen::new(display, 32, 32);
+    let viewproj = pixel_viewproj(target.width, target.height);
+    target.draw(display, |surface| scene.draw(surface, &viewproj));
+    target.read()
+}
+
+/// Every frame of the overground atlas side by side, which
+/// catches texture coordinates that bleed or are offset.
+fn atlas_frames<F: Facade + Clone>(display: &F) -> RgbaImage {
+    let atlas = Rc::new(TextureAtlas::from_packed(
+        "resources/overground.png",
+        "resources/overground.json",
+        display));
+    let mut scene = Scene::new(display, atlas);
+    scene.add_sprite("dirt", "dirt");
+    scene.add_sprite("grass", "grass");
+    scene.with_sprite_mut("grass", |x| x.set_position(16.0, 0.0));
+
+    let target = Offscreen::new(display, 32, 16);
+    let viewproj = pixel_viewproj(target.width, target.height);
+    target.draw(display, |surface| scene.draw(surface, &viewproj));
+    target.read()
+}
+
+fn cases<F: Facade + Clone>() -> Vec<Case<F>> {
+    vec![
+        Case { name: "tilemap_checkerboard", render: tilemap_checkerboard::<F> },
+        Case { name: "scene_depth_order", render: scene_depth_order::<F> },
+        Case { name: "atlas_frames", render: atlas_frames::<F> },
+    ]
+}
+
+/// Render every case and compare it against `<dir>/<name>.png`.
+///
+/// When `bless` is set the reference images are overwritten
+/// with the current output instead. On a mismatch the output
+/// is written to `<dir>/<name>.actual.png` for inspection.
+///
+/// Returns the number of cases that failed, counting any
+/// that couldn't be written.
+pub fn run<F, P>(display: &F, dir: P, bless: bool) -> usize
+    where F: Facade + Clone,
+          P: AsRef<Path> {
+    let dir = dir.as_ref();
+    let tolerance = Tolerance::default();
+    // both blessing and failures write into the directory
+    if let Err(err) = fs::create_dir_all(dir) {
+        println!("Could not create `{}`: {}", dir.display(), err);
+        return cases::<F>().len();
+    }
+
+    let mut failures = 0;
+    for case in cases::<F>() {
+        let actual = (case.render)(display);
+        let reference = dir.join(format!("{}.png", case.name));
+        if bless {
+            match actual.save(&reference) {
+                Ok(()) => println!("{}: blessed", case.name),
+                Err(err) => {
+                    failures += 1;
+                    println!("{}: could not write `{}`: {}", case.name, reference.display(), err);
+                }
+            }
+            continue;
+        }
+
+        match headless::compare_to_file(&actual, &reference, tolerance) {
+            Ok(()) => println!("{}: ok", case.name),
+            Err(err) => {
+                failures += 1;
+                let output = dir.join(format!("{}.actual.png", case.name));
+                match actual.save(&output) {
+                    Ok(()) => println!("{}: FAILED: {} (see `{}`)", case.name, err, output.display()),
+                    Err(save_err) => println!("{}: FAILED: {} (could not write `{}`: {})",
+                        case.name, err, output.display(), save_err)
+                }
+            }
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use glium::DisplayBuild;
+    use glium::glutin;
+
+    /// Blessed with `splore --golden resources/golden --bless`.
+    const REFERENCE_DIR: &'static str = "resources/golden";
+
+    #[test]
+    fn matches_reference_images() {
+        // machines without any OpenGL can't create even a
+        // headless context, so there is nothing to check
+        let display = match glutin::HeadlessRendererBuilder::new(256, 256).build_glium() {
+            Ok(display) => display,
+            Err(err) => {
+                println!("Skipping golden images without a headless context: {:?}", err);
+                return;
+            }
+        };
+        assert_eq!(super::run(&display, REFERENCE_DIR, false), 0);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+use std::path::Path;
+
+use glium::Surface;
+use glium::backend::Facade;
+use glium::framebuffer::{DepthRenderBuffer, SimpleFrameBuffer};
+use glium::texture::{DepthFormat, RawImage2d, Texture2d};
+use image;
+use image::{ImageBuffer, Rgba};
+
+pub type RgbaImage = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+/// A colour and depth target that lives entirely on the GPU
+/// so that we can render without a window.
+///
+/// Combined with glium's headless backend this works on
+/// machines without a display, including Mesa's software
+/// rasterizer.
+pub struct Offscreen {
+    pub width: u32,
+    pub height: u32,
+    color: Texture2d,
+    depth: DepthRenderBuffer
+}
+
+impl Offscreen {
+    pub fn new<F>(display: &F, width: u32, height: u32) -> Offscreen
+        where F: Facade {
+        let color = Texture2d::empty(display, width, height)
+            .ok().expect("Could not create offscreen colour texture");
+        let depth = DepthRenderBuffer::new(display, DepthFormat::I24, width, height)
+            .ok().expect("Could not create offscreen depth buffer");
+        Offscreen {
+            width: width,
+            height: height,
+            color: color,
+            depth: depth
+        }
+    }
+
+    /// Clear the target and hand a surface for it to `f`.
+    pub fn draw<F, D>(&self, display: &F, draw: D)
+        where F: Facade,
+              D: FnOnce(&mut SimpleFrameBuffer) {
+        let mut surface = SimpleFrameBuffer::with_depth_buffer(display, &self.color, &self.depth)
+            .ok().expect("Could not create offscreen framebuffer");
+        surface.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
+        draw(&mut surface);
+    }
+
+    /// Read the rendered pixels back from the GPU.
+    pub fn read(&self) -> RgbaImage {
+        let raw: RawImage2d<u8> = self.color.read();
+        from_raw(raw)
+    }
+}
+
+/// Convert pixels read back from OpenGL into an image.
+///
+/// OpenGL stores rows bottom to top so they are flipped
+/// to match the row order used by image files.
+pub fn from_raw(raw: RawImage2d<u8>) -> RgbaImage {
+    let (width, height) = (raw.width, raw.height);
+    let stride = width as usize * 4;
+    let mut pixels = Vec::with_capacity(stride * height as usize);
+    for row in raw.data.chunks(stride).rev() {
+        pixels.extend(row.iter().cloned());
+    }
+    ImageBuffer::from_raw(width, height, pixels)
+        .expect("Pixel data does not match image dimensions")
+}
+
+/// How far an image may stray from its reference and
+/// still be considered a match.
+#[derive(Copy, Clone, Debug)]
+pub struct Tolerance {
+    /// Largest difference allowed in any single channel.
+    pub channel: u8,
+    /// Number of pixels allowed to exceed `channel`.
+    pub pixels: usize
+}
+
+impl Default for Tolerance {
+    fn default() -> Tolerance {
+        Tolerance {
+            channel: 2,
+            pixels: 0
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CompareError {
+    /// The reference image could not be loaded.
+    Missing(image::ImageError),
+    Size {
+        expected: (u32, u32),
+        actual: (u32, u32)
+    },
+    Pixels {
+        mismatched: usize,
+        max_difference: u8
+    }
+}
+
+impl fmt::Display for CompareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompareError::Missing(ref err) => write!(f, "Could not load reference image: {}", err),
+            CompareError::Size { expected, actual } =>
+                write!(f, "Expected a {}x{} image, got {}x{}", expected.0, expected.1, actual.0, actual.1),
+            CompareError::Pixels { mismatched, max_difference } =>
+                write!(f, "{} pixels differ, by up to {}", mismatched, max_difference),
+        }
+    }
+}
+
+/// Compare two images channel by channel.
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage, tolerance: Tolerance) -> Result<(), CompareError> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(CompareError::Size {
+            expected: expected.dimensions(),
+            actual: actual.dimensions()
+        });
+    }
+
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+    for (a, e) in actual.pixels().zip(expected.pixels()) {
+        let difference = a.data.iter().zip(e.data.iter())
+            .map(|(&x, &y)| if x > y { x - y } else { y - x })
+            .max()
+            .unwrap_or(0);
+        if difference > tolerance.channel {
+            mismatched += 1;
+        }
+        if difference > max_difference {
+            max_difference = difference;
+        }
+    }
+
+    if mismatched > tolerance.pixels {
+        Err(CompareError::Pixels {
+            mismatched: mismatched,
+            max_difference: max_difference
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Compare an image against the reference PNG at `path`.
+pub fn compare_to_file<P>(actual: &RgbaImage, path: P, tolerance: Tolerance) -> Result<(), CompareError>
+    where P: AsRef<Path> {
+    let expected = try!(image::open(path).map_err(CompareError::Missing)).to_rgba();
+    compare(actual, &expected, tolerance)
+}
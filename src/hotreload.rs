@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Watches files for changes by polling their modification
+/// times, which works the same on every platform without
+/// needing any OS specific notification service.
+pub struct Watcher {
+    files: HashMap<PathBuf, Option<SystemTime>>,
+    interval: Duration,
+    last_poll: Instant
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+impl Watcher {
+    /// Create a Watcher that checks its files at most once
+    /// every `interval`.
+    pub fn new(interval: Duration) -> Watcher {
+        Watcher {
+            files: HashMap::new(),
+            interval: interval,
+            last_poll: Instant::now()
+        }
+    }
+
+    /// Start watching a file, if it isn't already.
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        if !self.files.contains_key(path) {
+            self.files.insert(path.to_path_buf(), modified(path));
+        }
+    }
+
+    pub fn unwatch<P: AsRef<Path>>(&mut self, path: P) {
+        self.files.remove(path.as_ref());
+    }
+
+    /// Report a file from `poll` again on the next check, e.g.
+    /// when reloading it failed because it was caught half
+    /// written and may not be modified again.
+    pub fn retry<P: AsRef<Path>>(&mut self, path: P) {
+        if let Some(time) = self.files.get_mut(path.as_ref()) {
+            *time = None;
+        }
+    }
+
+    /// Check the watched files, returning those that have
+    /// changed since the last check.
+    ///
+    /// Files that are missing are not reported until they
+    /// reappear, as tools often delete a file before writing
+    /// its replacement.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        if self.last_poll.elapsed() < self.interval {
+            return changed;
+        }
+        self.last_poll = Instant::now();
+
+        for (path, time) in self.files.iter_mut() {
+            let current = modified(path);
+            if current.is_some() && current != *time {
+                changed.push(path.clone());
+            }
+            *time = current;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{modified, Watcher};
+
+    /// A file in an empty directory for a test.
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("watched.txt");
+        File::create(&path).unwrap();
+        path
+    }
+
+    /// Write to a file until its modification time changes,
+    /// which some file systems only store to the second.
+    fn rewrite(path: &Path) {
+        let before = modified(path);
+        loop {
+            File::create(path).unwrap().write_all(b"changed").unwrap();
+            if modified(path) != before {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn watcher(path: &Path) -> Watcher {
+        let mut watcher = Watcher::new(Duration::from_millis(0));
+        watcher.watch(path);
+        watcher
+    }
+
+    #[test]
+    fn reports_a_change_once() {
+        let path = temp_file("splore-watch-change");
+        let mut watcher = watcher(&path);
+        assert!(watcher.poll().is_empty());
+        rewrite(&path);
+        assert_eq!(watcher.poll(), vec![path.clone()]);
+        assert!(watcher.poll().is_empty());
+    }
+
+    #[test]
+    fn reports_a_deleted_file_when_it_reappears() {
+        let path = temp_file("splore-watch-delete");
+        let mut watcher = watcher(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.poll().is_empty());
+        File::create(&path).unwrap();
+        assert_eq!(watcher.poll(), vec![path.clone()]);
+        assert!(watcher.poll().is_empty());
+    }
+
+    #[test]
+    fn retry_reports_a_file_again() {
+        let path = temp_file("splore-watch-retry");
+        let mut watcher = watcher(&path);
+        rewrite(&path);
+        assert_eq!(watcher.poll(), vec![path.clone()]);
+        watcher.retry(&path);
+        assert_eq!(watcher.poll(), vec![path.clone()]);
+        assert!(watcher.poll().is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_interval() {
+        let path = temp_file("splore-watch-interval");
+        let mut watcher = Watcher::new(Duration::from_secs(3600));
+        watcher.watch(&path);
+        rewrite(&path);
+        assert!(watcher.poll().is_empty());
+    }
+}
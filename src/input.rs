@@ -0,0 +1,475 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::default::Default;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use glium::glutin::ElementState::Pressed;
+use glium::glutin::{Event, MouseButton, VirtualKeyCode};
+use serde_json;
+use serde_json::value::Value;
+
+/// A physical button that can be bound to an action.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton)
+}
+
+/// A pair of button sets that together produce a value
+/// in the range `[-1, 1]`.
+#[derive(Clone, Debug, Default)]
+pub struct Axis {
+    pub negative: Vec<Button>,
+    pub positive: Vec<Button>
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The config did not have the expected shape.
+    Format(String),
+    /// A button name that we don't know how to map.
+    UnknownButton(String)
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BindingsError::Io(ref err) => write!(f, "IO error: {}", err),
+            BindingsError::Json(ref err) => write!(f, "JSON error: {}", err),
+            BindingsError::Format(ref msg) => write!(f, "Invalid bindings: {}", msg),
+            BindingsError::UnknownButton(ref name) => write!(f, "Unknown button: `{}`", name),
+        }
+    }
+}
+
+impl From<io::Error> for BindingsError {
+    fn from(err: io::Error) -> BindingsError {
+        BindingsError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for BindingsError {
+    fn from(err: serde_json::Error) -> BindingsError {
+        BindingsError::Json(err)
+    }
+}
+
+/// Maps named actions and axes onto physical buttons.
+///
+/// Bindings are loaded from a JSON file of the form:
+///
+/// ```json
+/// {
+///     "actions": { "activate": ["Space", "MouseLeft"] },
+///     "axes": { "horizontal": { "negative": ["Left"], "positive": ["Right"] } }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    actions: HashMap<String, Vec<Button>>,
+    axes: HashMap<String, Axis>
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        let mut bindings = Bindings::new();
+        bindings.bind("quit", Button::Key(VirtualKeyCode::Escape));
+        bindings.bind("activate", Button::Key(VirtualKeyCode::Space));
+        bindings.bind("activate", Button::Key(VirtualKeyCode::Return));
+        bindings.bind("activate", Button::Mouse(MouseButton::Left));
+        bindings.bind("record", Button::Key(VirtualKeyCode::F11));
+        bindings.bind("screenshot", Button::Key(VirtualKeyCode::F12));
+        bindings.bind("debug", Button::Key(VirtualKeyCode::F3));
+        bindings.bind_axis("horizontal", Button::Key(VirtualKeyCode::Left), Button::Key(VirtualKeyCode::Right));
+        bindings.bind_axis("vertical", Button::Key(VirtualKeyCode::Down), Button::Key(VirtualKeyCode::Up));
+        bindings
+    }
+}
+
+impl Bindings {
+    /// Create an empty set of bindings.
+    pub fn new() -> Bindings {
+        Bindings {
+            actions: HashMap::new(),
+            axes: HashMap::new()
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Bindings, BindingsError> {
+        let mut file = try!(File::open(path));
+        let mut json = String::new();
+        try!(file.read_to_string(&mut json));
+        Bindings::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Bindings, BindingsError> {
+        let data: Value = try!(serde_json::from_str(json));
+        let mut bindings = Bindings::new();
+
+        if let Some(actions) = data.find("actions") {
+            let actions = try!(actions.as_object()
+                .ok_or(BindingsError::Format("`actions` is not an object".into())));
+            for (action, buttons) in actions.iter() {
+                for button in try!(parse_buttons(buttons)) {
+                    bindings.bind(action, button);
+                }
+            }
+        }
+
+        if let Some(axes) = data.find("axes") {
+            let axes = try!(axes.as_object()
+                .ok_or(BindingsError::Format("`axes` is not an object".into())));
+            for (name, axis) in axes.iter() {
+                let negative = match axis.find("negative") {
+                    Some(buttons) => try!(parse_buttons(buttons)),
+                    None => Vec::new()
+                };
+                let positive = match axis.find("positive") {
+                    Some(buttons) => try!(parse_buttons(buttons)),
+                    None => Vec::new()
+                };
+                bindings.axes.insert(name.clone(), Axis { negative: negative, positive: positive });
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    /// Add a button to the given action.
+    pub fn bind(&mut self, action: &str, button: Button) {
+        let buttons = self.actions.entry(action.to_string()).or_insert(Vec::new());
+        if !buttons.contains(&button) {
+            buttons.push(button);
+        }
+    }
+
+    /// Add a pair of buttons to the given axis.
+    pub fn bind_axis(&mut self, name: &str, negative: Button, positive: Button) {
+        let axis = self.axes.entry(name.to_string()).or_insert(Axis::default());
+        axis.negative.push(negative);
+        axis.positive.push(positive);
+    }
+
+    /// Remove every button bound to the given action.
+    pub fn unbind(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    pub fn get_action(&self, action: &str) -> Option<&[Button]> {
+        self.actions.get(action).map(|x| &x[..])
+    }
+
+    pub fn get_axis(&self, name: &str) -> Option<&Axis> {
+        self.axes.get(name)
+    }
+}
+
+fn parse_buttons(value: &Value) -> Result<Vec<Button>, BindingsError> {
+    let names = try!(value.as_array()
+        .ok_or(BindingsError::Format("expected an array of button names".into())));
+    let mut buttons = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        let name = try!(name.as_string()
+            .ok_or(BindingsError::Format("button names must be strings".into())));
+        let button = try!(parse_button(name)
+            .ok_or(BindingsError::UnknownButton(name.to_string())));
+        buttons.push(button);
+    }
+    Ok(buttons)
+}
+
+/// Parse a button from the name used in the bindings config.
+///
+/// Keys use the name of their `VirtualKeyCode` and mouse
+/// buttons are prefixed with `Mouse`, e.g. `MouseLeft`.
+pub fn parse_button(name: &str) -> Option<Button> {
+    use glium::glutin::VirtualKeyCode::*;
+
+    let mouse = match name {
+        "MouseLeft" => Some(MouseButton::Left),
+        "MouseRight" => Some(MouseButton::Right),
+        "MouseMiddle" => Some(MouseButton::Middle),
+        _ => None
+    };
+    if let Some(button) = mouse {
+        return Some(Button::Mouse(button));
+    }
+
+    let key = match name {
+        "Key1" => Key1, "Key2" => Key2, "Key3" => Key3, "Key4" => Key4, "Key5" => Key5,
+        "Key6" => Key6, "Key7" => Key7, "Key8" => Key8, "Key9" => Key9, "Key0" => Key0,
+        "A" => A, "B" => B, "C" => C, "D" => D, "E" => E, "F" => F, "G" => G,
+        "H" => H, "I" => I, "J" => J, "K" => K, "L" => L, "M" => M, "N" => N,
+        "O" => O, "P" => P, "Q" => Q, "R" => R, "S" => S, "T" => T, "U" => U,
+        "V" => V, "W" => W, "X" => X, "Y" => Y, "Z" => Z,
+        "F1" => F1, "F2" => F2, "F3" => F3, "F4" => F4, "F5" => F5, "F6" => F6,
+        "F7" => F7, "F8" => F8, "F9" => F9, "F10" => F10, "F11" => F11, "F12" => F12,
+        "Escape" => Escape,
+        "Left" => Left, "Up" => Up, "Right" => Right, "Down" => Down,
+        "Back" => Back, "Return" => Return, "Space" => Space, "Tab" => Tab,
+        "Insert" => Insert, "Delete" => Delete, "Home" => Home, "End" => End,
+        "PageUp" => PageUp, "PageDown" => PageDown,
+        "LShift" => LShift, "RShift" => RShift,
+        "LControl" => LControl, "RControl" => RControl,
+        "LAlt" => LAlt, "RAlt" => RAlt,
+        "Comma" => Comma, "Period" => Period, "Slash" => Slash,
+        "Semicolon" => Semicolon, "Minus" => Minus, "Equals" => Equals,
+        "Numpad0" => Numpad0, "Numpad1" => Numpad1, "Numpad2" => Numpad2,
+        "Numpad3" => Numpad3, "Numpad4" => Numpad4, "Numpad5" => Numpad5,
+        "Numpad6" => Numpad6, "Numpad7" => Numpad7, "Numpad8" => Numpad8,
+        "Numpad9" => Numpad9,
+        _ => return None
+    };
+    Some(Button::Key(key))
+}
+
+/// A snapshot of the resolved actions and axes at a
+/// single point in time.
+///
+/// This is what the simulation consumes each tick, which
+/// keeps it independent of the window so that it can be
+/// recorded and replayed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputState {
+    pub pressed: BTreeSet<String>,
+    pub axes: BTreeMap<String, f32>
+}
+
+impl InputState {
+    #[inline]
+    pub fn is_pressed(&self, action: &str) -> bool {
+        self.pressed.contains(action)
+    }
+
+    #[inline]
+    pub fn axis(&self, name: &str) -> f32 {
+        self.axes.get(name).cloned().unwrap_or(0.0)
+    }
+}
+
+/// Tracks which buttons are held and resolves them into
+/// the state of each bound action.
+///
+/// Window events are fed in through `handle_event` and
+/// `update` should be called once per frame after all
+/// events have been handled so that the pressed and
+/// released edges line up with frames.
+pub struct Input {
+    bindings: Bindings,
+    held: HashSet<Button>,
+    current: HashSet<String>,
+    previous: HashSet<String>,
+    axes: HashMap<String, f32>
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Input {
+        Input {
+            bindings: bindings,
+            held: HashSet::new(),
+            current: HashSet::new(),
+            previous: HashSet::new(),
+            axes: HashMap::new()
+        }
+    }
+
+    #[inline]
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    #[inline]
+    pub fn bindings_mut(&mut self) -> &mut Bindings {
+        &mut self.bindings
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::KeyboardInput(state, _, Some(key)) => self.set_held(Button::Key(key), state == Pressed),
+            Event::MouseInput(state, button) => self.set_held(Button::Mouse(button), state == Pressed),
+            // we won't see the release events while unfocused
+            Event::Focused(false) => self.held.clear(),
+            _ => {}
+        }
+    }
+
+    fn set_held(&mut self, button: Button, held: bool) {
+        if held {
+            self.held.insert(button);
+        } else {
+            self.held.remove(&button);
+        }
+    }
+
+    /// Resolve the held buttons into action states for this frame.
+    pub fn update(&mut self) {
+        let mut current = HashSet::with_capacity(self.current.len());
+        for (action, buttons) in self.bindings.actions.iter() {
+            if buttons.iter().any(|x| self.held.contains(x)) {
+                current.insert(action.clone());
+            }
+        }
+        self.previous = ::std::mem::replace(&mut self.current, current);
+
+        self.axes.clear();
+        for (name, axis) in self.bindings.axes.iter() {
+            let mut value = 0.0;
+            if axis.negative.iter().any(|x| self.held.contains(x)) {
+                value -= 1.0;
+            }
+            if axis.positive.iter().any(|x| self.held.contains(x)) {
+                value += 1.0;
+            }
+            self.axes.insert(name.clone(), value);
+        }
+    }
+
+    /// Take a snapshot of the current action and axis values.
+    pub fn state(&self) -> InputState {
+        InputState {
+            pressed: self.current.iter().cloned().collect(),
+            axes: self.axes.iter().map(|(k, v)| (k.clone(), *v)).collect()
+        }
+    }
+
+    /// Whether the action is currently held down.
+    #[inline]
+    pub fn is_pressed(&self, action: &str) -> bool {
+        self.current.contains(action)
+    }
+
+    /// Whether the action went down this frame.
+    #[inline]
+    pub fn just_pressed(&self, action: &str) -> bool {
+        self.current.contains(action) && !self.previous.contains(action)
+    }
+
+    /// Whether the action went up this frame.
+    #[inline]
+    pub fn just_released(&self, action: &str) -> bool {
+        !self.current.contains(action) && self.previous.contains(action)
+    }
+
+    /// The value of the axis, which is `0.0` when both or
+    /// neither of its directions are held.
+    #[inline]
+    pub fn axis(&self, name: &str) -> f32 {
+        self.axes.get(name).cloned().unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glium::glutin::ElementState::{Pressed, Released};
+    use glium::glutin::{Event, VirtualKeyCode};
+
+    fn key(input: &mut Input, key: VirtualKeyCode, pressed: bool) {
+        let state = if pressed { Pressed } else { Released };
+        input.handle_event(&Event::KeyboardInput(state, 0, Some(key)));
+    }
+
+    #[test]
+    fn pressed_and_released_edges() {
+        let mut input = Input::new(Bindings::default());
+        key(&mut input, VirtualKeyCode::Space, true);
+        input.update();
+        assert!(input.is_pressed("activate"));
+        assert!(input.just_pressed("activate"));
+
+        // still held on the next frame
+        input.update();
+        assert!(input.is_pressed("activate"));
+        assert!(!input.just_pressed("activate"));
+        assert!(!input.just_released("activate"));
+
+        key(&mut input, VirtualKeyCode::Space, false);
+        input.update();
+        assert!(!input.is_pressed("activate"));
+        assert!(input.just_released("activate"));
+
+        input.update();
+        assert!(!input.just_released("activate"));
+    }
+
+    #[test]
+    fn action_stays_down_while_any_button_is_held() {
+        let mut input = Input::new(Bindings::default());
+        key(&mut input, VirtualKeyCode::Space, true);
+        input.update();
+        key(&mut input, VirtualKeyCode::Return, true);
+        key(&mut input, VirtualKeyCode::Space, false);
+        input.update();
+        assert!(input.is_pressed("activate"));
+        assert!(!input.just_released("activate"));
+    }
+
+    #[test]
+    fn opposing_axis_buttons_cancel_out() {
+        let mut input = Input::new(Bindings::default());
+        key(&mut input, VirtualKeyCode::Left, true);
+        input.update();
+        assert_eq!(input.axis("horizontal"), -1.0);
+
+        key(&mut input, VirtualKeyCode::Right, true);
+        input.update();
+        assert_eq!(input.axis("horizontal"), 0.0);
+
+        key(&mut input, VirtualKeyCode::Left, false);
+        input.update();
+        assert_eq!(input.axis("horizontal"), 1.0);
+        assert_eq!(input.axis("vertical"), 0.0);
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let mut input = Input::new(Bindings::default());
+        key(&mut input, VirtualKeyCode::Space, true);
+        input.update();
+        input.handle_event(&Event::Focused(false));
+        input.update();
+        assert!(input.just_released("activate"));
+    }
+
+    #[test]
+    fn from_json_reads_actions_and_axes() {
+        let bindings = Bindings::from_json(r#"{
+            "actions": { "jump": ["Space", "MouseRight"] },
+            "axes": { "horizontal": { "negative": ["A"], "positive": ["D"] } }
+        }"#).unwrap();
+        assert_eq!(bindings.get_action("jump"),
+            Some(&[Button::Key(VirtualKeyCode::Space), Button::Mouse(MouseButton::Right)][..]));
+        let axis = bindings.get_axis("horizontal").unwrap();
+        assert_eq!(axis.negative, vec![Button::Key(VirtualKeyCode::A)]);
+        assert_eq!(axis.positive, vec![Button::Key(VirtualKeyCode::D)]);
+    }
+
+    #[test]
+    fn from_json_rejects_unknown_buttons() {
+        match Bindings::from_json(r#"{ "actions": { "jump": ["Hyper"] } }"#) {
+            Err(BindingsError::UnknownButton(ref name)) if name == "Hyper" => {},
+            other => panic!("expected an unknown button error, got {:?}", other)
+        }
+        match Bindings::from_json(r#"{ "axes": { "horizontal": { "negative": ["MouseBack"] } } }"#) {
+            Err(BindingsError::UnknownButton(ref name)) if name == "MouseBack" => {},
+            other => panic!("expected an unknown button error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn from_json_rejects_malformed_bindings() {
+        match Bindings::from_json(r#"{ "actions": ["Space"] }"#) {
+            Err(BindingsError::Format(_)) => {},
+            other => panic!("expected a format error, got {:?}", other)
+        }
+        match Bindings::from_json(r#"{ "actions": { "jump": [1] } }"#) {
+            Err(BindingsError::Format(_)) => {},
+            other => panic!("expected a format error, got {:?}", other)
+        }
+    }
+}
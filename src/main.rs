@@ -1,3 +1,5 @@
+extern crate clap;
+extern crate gif;
 #[macro_use]
 extern crate glium;
 extern crate glium_text;
@@ -7,26 +9,53 @@ extern crate serde_json;
 
 use std::collections::HashMap;
 use std::default::Default;
+use std::path::PathBuf;
+use std::process;
 use std::rc::{Rc};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::{App, Arg};
 
 use glium::{IndexBuffer, Program, Surface, VertexBuffer};
 use glium::backend::Facade;
 use glium::glutin;
-use glium::glutin::ElementState::Pressed;
 use glium::glutin::Event;
-use glium::glutin::VirtualKeyCode;
 use glium::index::PrimitiveType;
 use glium::texture::{CompressedSrgbTexture2d};
 use na::{Iso3, Ortho3, Pnt2, Pnt3, Vec3};
 use na::{ToHomogeneous};
 
+use assets::{Assets, Reloaded};
+use capture::{Capture, CaptureError};
+use debug::{DebugDraw, DebugOverlay, Stats};
+use gameloop::{GameLoop, SystemClock};
+use hotreload::{Watcher};
+use input::{Bindings, Input};
+use replay::{Recording};
 use scene::{Scene};
+use text::{Font};
 use textureatlas::{Frame, TextureAtlas};
 use tilemap::{Tile, TileMap};
+use world::{World};
 
+mod assets;
+mod atlasfile;
+mod bitmapfont;
+mod capture;
+mod debug;
+mod fnv;
+mod gameloop;
+mod golden;
+mod headless;
+mod hotreload;
+mod input;
+mod replay;
+mod rng;
 mod scene;
+mod text;
 mod textureatlas;
 mod tilemap;
+mod world;
 
 pub enum OvergroundTile {
     Dirt,
@@ -66,17 +95,81 @@ pub struct Mesh<V>
 //     // }
 // }
 
-struct Input {
-    pub left: bool,
-    pub right: bool,
-    pub up: bool,
-    pub down: bool,
-    pub activate: bool
+fn report_captures(result: Result<Vec<PathBuf>, CaptureError>) {
+    match result {
+        Ok(paths) => for path in paths {
+            println!("Saved capture to `{}`", path.display());
+        },
+        Err(err) => println!("Could not save capture: {}", err)
+    }
 }
 
+/// Length of a simulation tick in seconds.
+const TICK: f64 = 1.0 / 60.0;
+
+/// Number of frames captured into a GIF by the record hotkey.
+const RECORD_FRAMES: usize = 180;
+
+/// Number of frames shown in the debug overlay's graph.
+const FRAME_HISTORY: usize = 120;
+
 fn main() {
     use glium::DisplayBuild;
 
+    let matches = App::new("splore")
+        .arg(Arg::with_name("RECORD")
+            .long("record")
+            .takes_value(true)
+            .help("Record input to the given file"))
+        .arg(Arg::with_name("REPLAY")
+            .long("replay")
+            .takes_value(true)
+            .conflicts_with("RECORD")
+            .help("Replay a recording without a window and check the final world state"))
+        .arg(Arg::with_name("DEV")
+            .long("dev")
+            .help("Reload atlases and maps when they change on disk"))
+        .arg(Arg::with_name("GOLDEN")
+            .long("golden")
+            .takes_value(true)
+            .help("Render the golden image cases offscreen and compare them to the PNGs in the given directory, normally resources/golden"))
+        .arg(Arg::with_name("BLESS")
+            .long("bless")
+            .requires("GOLDEN")
+            .help("Overwrite the golden images with the current output"))
+        .arg(Arg::with_name("FONT")
+            .long("font")
+            .takes_value(true)
+            .help("TTF font used for the debug overlay's text instead of its built-in pixel font"))
+        .get_matches();
+
+    if let Some(dir) = matches.value_of("GOLDEN") {
+        let display = glutin::HeadlessRendererBuilder::new(256, 256)
+            .build_glium()
+            .unwrap();
+        let failures = golden::run(&display, dir, matches.is_present("BLESS"));
+        if failures > 0 {
+            println!("{} golden image cases failed", failures);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = matches.value_of("REPLAY") {
+        let recording = Recording::load(path).unwrap_or_else(|err| {
+            println!("Could not load recording `{}`: {}", path, err);
+            process::exit(1);
+        });
+        match recording.verify() {
+            Ok(world) => println!("Replayed {} ticks, world hash {:016x}", world.ticks(), world.hash()),
+            Err(err) => {
+                println!("Replay of `{}` failed: {}", path, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     let window = glutin::WindowBuilder::new()
         .with_dimensions(640, 480)
         .with_title("splore".into())
@@ -90,33 +183,15 @@ fn main() {
     // let atlas = TextureAtlas::new(
     //     atlas_texture,
     //     tile_uvs);
-    let atlas = TextureAtlas::from_packed(
-        "resources/overground.png",
-        "resources/overground.json",
-        &window);
-    let mut tiles = Vec::new();
-    for x in 0..10 {
-        for y in 0..10 {
-            tiles.push(OvergroundTile::Grass);
-            // if (x + y) % 2 == 0 { 
-            //     tiles.push(OvergroundTile::Grass);
-            // } else {
-            //     tiles.push(OvergroundTile::Dirt);
-            // }
-        }
-    }
-    let tilemap = TileMap::new(
-        &window,
-        10, 10,
-        16, 16,
-        tiles,
-        atlas
-    );
-
-    let spritesheet = TextureAtlas::from_packed(
-        "resources/spritesheet.png",
-        "resources/spritesheet.json",
-        &window);
+    let mut assets = Assets::new(&window);
+    assets.register("overground", "resources/overground_map.json");
+    assets.register("spritesheet", "resources/spritesheet.json");
+
+    let mut map = assets.map("overground").unwrap_or_else(|err| panic!("{}", err));
+    let atlas = assets.atlas(&map.atlas).unwrap_or_else(|err| panic!("{}", err));
+    let mut tilemap = TileMap::from_data(&window, &map, atlas);
+
+    let spritesheet = assets.atlas("spritesheet").unwrap_or_else(|err| panic!("{}", err));
     let mut scene = Scene::new(&window, spritesheet);
     scene.add_sprite("player", "player");
     scene.add_sprite("player2", "player");
@@ -126,52 +201,157 @@ fn main() {
     let proj = Ortho3::new(width * 2.0, height * 2.0, -1.0, 1.0);
     // let proj = Ortho3::new(width, height, -1.0, 1.0);
     let mut view = Iso3::new(na::zero(), na::zero());
-    let mut focus = Pnt2::new(width / 2.0, height / 2.0);
+    let focus = Pnt2::new(width / 2.0, height / 2.0);
     // let mut focus = Pnt2::new(0.0, 0.0);
 
-    let mut input = Input {
-        left: false,
-        right: false,
-        up: false,
-        down: false,
-        activate: false
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let mut world = World::new(seed, focus);
+    let mut recording = matches.value_of("RECORD").map(|_| Recording::new(seed, TICK, focus));
+
+    let bindings = Bindings::load("resources/input.json").unwrap_or_else(|err| {
+        println!("Could not load input bindings, using defaults: {}", err);
+        Bindings::default()
+    });
+    let mut input = Input::new(bindings);
+
+    let mut game_loop = GameLoop::new(SystemClock::new(), TICK);
+    let mut capture = Capture::new("captures");
+
+    let font = matches.value_of("FONT").and_then(|path| {
+        Font::load(&window, path, 24).map_err(|err| {
+            println!("Could not load font `{}`: {}", path, err);
+        }).ok()
+    });
+    let mut overlay = DebugOverlay::new(&window, font);
+    let mut shapes = DebugDraw::new(&window);
+    let mut stats = Stats::new(FRAME_HISTORY);
+    let mut last_frame = Instant::now();
+
+    let mut watcher = if matches.is_present("DEV") {
+        let mut watcher = Watcher::new(Duration::from_millis(500));
+        for path in assets.paths() {
+            watcher.watch(path);
+        }
+        Some(watcher)
+    } else {
+        None
     };
 
     'main: loop {
-        view.look_at_z(&Pnt3::new(focus.x, focus.y, -1.0), &Pnt3::new(focus.x, focus.y, 0.0), &Vec3::y());
-        let viewproj = proj.to_mat() * na::inv(&view.to_homogeneous()).unwrap();
-
-        let mut frame = window.draw();
-        frame.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
-        tilemap.draw(&mut frame, &viewproj);
-        scene.draw(&mut frame, &viewproj);
-        frame.finish().unwrap();
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+        stats.begin_frame(elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9);
 
-        let speed = 3.0;
         for event in window.poll_events() {
             match event {
                 Event::Closed => break 'main,
-                Event::KeyboardInput(state, _, Some(VirtualKeyCode::Escape)) => if state == Pressed {
-                    break 'main;
-                },
-                Event::KeyboardInput(state, _, Some(VirtualKeyCode::Right)) => input.right = state == Pressed,
-                Event::KeyboardInput(state, _, Some(VirtualKeyCode::Left)) => input.left = state == Pressed,
-                Event::KeyboardInput(state, _, Some(VirtualKeyCode::Up)) => input.up = state == Pressed,
-                Event::KeyboardInput(state, _, Some(VirtualKeyCode::Down)) => input.down = state == Pressed,
-                _ => {}
+                _ => input.handle_event(&event)
             }
         }
+        input.update();
 
-        if input.right {
-            focus.x += speed;
-        } else if input.left {
-            focus.x -= speed;
+        if input.just_pressed("quit") {
+            break 'main;
+        }
+        if input.just_pressed("screenshot") {
+            capture.request_screenshot();
+        }
+        if input.just_pressed("record") {
+            if capture.is_recording() {
+                capture.stop_recording();
+            } else {
+                capture.start_recording(RECORD_FRAMES);
+            }
         }
+        if input.just_pressed("debug") {
+            overlay.toggle();
+        }
+
+        if let Some(ref mut watcher) = watcher {
+            for path in watcher.poll() {
+                let reloaded = match assets.reload(&path) {
+                    Ok(reloaded) => reloaded,
+                    Err(err) => {
+                        println!("{}", err);
+                        watcher.retry(&path);
+                        continue;
+                    }
+                };
+                println!("Reloaded `{}`", path.display());
+                for asset in reloaded {
+                    match asset {
+                        Reloaded::Atlas { old, new } => {
+                            if let Err(err) = tilemap.reload_atlas(&window, &old, &new) {
+                                println!("Keeping the old tiles for `{}`: {}", path.display(), err);
+                            }
+                            scene.reload_atlas(&old, &new);
+                        },
+                        Reloaded::Map { old, new } => if Rc::ptr_eq(&old, &map) {
+                            match assets.atlas(&new.atlas) {
+                                Ok(atlas) => if let Err(err) = tilemap.set_data(&window, &new, atlas) {
+                                    println!("Keeping the old map for `{}`: {}", path.display(), err);
+                                },
+                                Err(err) => println!("{}", err)
+                            }
+                            // follow the latest map even if it couldn't be
+                            // used so that fixing it is picked up
+                            map = new;
+                        }
+                    }
+                }
+                // a map may have pulled in a new atlas
+                for path in assets.paths() {
+                    watcher.watch(path);
+                }
+            }
+        }
+
+        let state = input.state();
+        let alpha = game_loop.step(|dt| {
+            if let Some(ref mut recording) = recording {
+                recording.push(state.clone());
+            }
+            world.update(&state, dt as f32);
+            scene.update(dt as f32);
+        });
+
+        // blend between the last two simulated states so that
+        // movement stays smooth when ticks and frames don't line up
+        let camera = world.interpolated_focus(alpha);
+        view.look_at_z(&Pnt3::new(camera.x, camera.y, -1.0), &Pnt3::new(camera.x, camera.y, 0.0), &Vec3::y());
+        let viewproj = proj.to_mat() * na::inv(&view.to_homogeneous()).unwrap();
+
+        let mut frame = window.draw();
+        frame.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
+        tilemap.draw(&mut frame, &viewproj);
+        scene.draw(&mut frame, &viewproj);
+        if overlay.visible {
+            shapes.tile_grid(&tilemap, [1.0, 1.0, 1.0, 0.25]);
+            shapes.sprite_bounds(&scene, [0.0, 1.0, 0.0, 1.0]);
+        }
+        shapes.draw(&mut frame, &viewproj);
+
+        // only the tilemap and scene, debug shapes and text
+        // are drawn separately
+        stats.set("world batches", tilemap.batch_count() + scene.batch_count());
+        stats.set("sprites", scene.sprite_count());
+        stats.set("tiles", tilemap.tile_count());
+        stats.set("camera", format!("({:.1}, {:.1})", camera.x, camera.y));
+        overlay.draw(&mut frame, &stats);
+        frame.finish().unwrap();
+
+        report_captures(capture.process(&window));
+    }
+    // wait for any GIFs still being written
+    report_captures(capture.finish());
 
-        if input.up {
-            focus.y += speed;
-        } else if input.down {
-            focus.y -= speed;
+    if let (Some(path), Some(mut recording)) = (matches.value_of("RECORD"), recording) {
+        recording.finish(&world);
+        match recording.save(path) {
+            Ok(()) => println!("Saved recording of {} ticks to `{}`", recording.ticks.len(), path),
+            Err(err) => println!("Could not save recording to `{}`: {}", path, err)
         }
     }
 }
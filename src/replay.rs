@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use na::Pnt2;
+use serde_json;
+use serde_json::value::Value;
+
+use gameloop::{GameLoop, ManualClock};
+use input::InputState;
+use world::World;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The recording did not have the expected shape.
+    Format(String),
+    /// The replayed world did not end up where the recorded one did.
+    Mismatch {
+        expected: u64,
+        actual: u64
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Io(ref err) => write!(f, "IO error: {}", err),
+            ReplayError::Json(ref err) => write!(f, "JSON error: {}", err),
+            ReplayError::Format(ref msg) => write!(f, "Invalid recording: {}", msg),
+            ReplayError::Mismatch { expected, actual } =>
+                write!(f, "World hash mismatch: expected {:016x}, got {:016x}", expected, actual),
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> ReplayError {
+        ReplayError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ReplayError {
+    fn from(err: serde_json::Error) -> ReplayError {
+        ReplayError::Json(err)
+    }
+}
+
+/// The input for every tick of a play session along with
+/// everything else needed to reproduce it.
+pub struct Recording {
+    pub seed: u64,
+    pub dt: f64,
+    pub focus: Pnt2<f32>,
+    pub ticks: Vec<InputState>,
+    /// Hash of the world after the final tick.
+    pub hash: Option<u64>
+}
+
+impl Recording {
+    pub fn new(seed: u64, dt: f64, focus: Pnt2<f32>) -> Recording {
+        Recording {
+            seed: seed,
+            dt: dt,
+            focus: focus,
+            ticks: Vec::new(),
+            hash: None
+        }
+    }
+
+    /// Record the input used for a single tick.
+    #[inline]
+    pub fn push(&mut self, state: InputState) {
+        self.ticks.push(state);
+    }
+
+    /// Mark the recording as finished with the given world.
+    pub fn finish(&mut self, world: &World) {
+        self.hash = Some(world.hash());
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording, ReplayError> {
+        let mut file = try!(File::open(path));
+        let mut json = String::new();
+        try!(file.read_to_string(&mut json));
+        let data: Value = try!(serde_json::from_str(&json));
+
+        let seed = try!(data.find("seed").and_then(|x| x.as_u64())
+            .ok_or(ReplayError::Format("missing `seed`".into())));
+        let dt = try!(data.find("dt").and_then(|x| x.as_f64())
+            .ok_or(ReplayError::Format("missing `dt`".into())));
+        // a GameLoop can't step by this
+        if !dt.is_finite() || dt <= 0.0 {
+            return Err(ReplayError::Format(format!("`dt` should be a positive number of seconds, not {}", dt)));
+        }
+        let focus = try!(data.find("focus").and_then(|x| x.as_array())
+            .ok_or(ReplayError::Format("missing `focus`".into())));
+        if focus.len() != 2 {
+            return Err(ReplayError::Format("`focus` should be [x, y]".into()));
+        }
+        let focus = Pnt2::new(
+            try!(focus[0].as_f64().ok_or(ReplayError::Format("`focus` should be [x, y]".into()))) as f32,
+            try!(focus[1].as_f64().ok_or(ReplayError::Format("`focus` should be [x, y]".into()))) as f32);
+        let hash = data.find("hash").and_then(|x| x.as_u64());
+
+        let ticks = try!(data.find("ticks").and_then(|x| x.as_array())
+            .ok_or(ReplayError::Format("missing `ticks`".into())));
+        let mut states = Vec::with_capacity(ticks.len());
+        for tick in ticks.iter() {
+            let mut state = InputState::default();
+            if let Some(pressed) = tick.find("pressed").and_then(|x| x.as_array()) {
+                for action in pressed.iter() {
+                    let action = try!(action.as_string()
+                        .ok_or(ReplayError::Format("actions must be strings".into())));
+                    state.pressed.insert(action.to_string());
+                }
+            }
+            if let Some(axes) = tick.find("axes").and_then(|x| x.as_object()) {
+                for (name, value) in axes.iter() {
+                    let value = try!(value.as_f64()
+                        .ok_or(ReplayError::Format("axis values must be numbers".into())));
+                    state.axes.insert(name.clone(), value as f32);
+                }
+            }
+            states.push(state);
+        }
+
+        Ok(Recording {
+            seed: seed,
+            dt: dt,
+            focus: focus,
+            ticks: states,
+            hash: hash
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ReplayError> {
+        let mut ticks = Vec::with_capacity(self.ticks.len());
+        for state in self.ticks.iter() {
+            let mut tick = BTreeMap::new();
+            let pressed = state.pressed.iter().map(|x| Value::String(x.clone())).collect();
+            tick.insert("pressed".to_string(), Value::Array(pressed));
+            let axes = state.axes.iter().map(|(k, v)| (k.clone(), Value::F64(*v as f64))).collect();
+            tick.insert("axes".to_string(), Value::Object(axes));
+            ticks.push(Value::Object(tick));
+        }
+
+        let mut data = BTreeMap::new();
+        data.insert("seed".to_string(), Value::U64(self.seed));
+        data.insert("dt".to_string(), Value::F64(self.dt));
+        data.insert("focus".to_string(), Value::Array(vec![
+            Value::F64(self.focus.x as f64),
+            Value::F64(self.focus.y as f64)]));
+        data.insert("ticks".to_string(), Value::Array(ticks));
+        if let Some(hash) = self.hash {
+            data.insert("hash".to_string(), Value::U64(hash));
+        }
+
+        let mut file = try!(File::create(path));
+        try!(serde_json::to_writer_pretty(&mut file, &Value::Object(data)));
+        Ok(())
+    }
+
+    /// Run the recorded input back through the simulation.
+    ///
+    /// The loop is driven by a ManualClock so this runs as
+    /// fast as possible and without a window.
+    pub fn replay(&self) -> World {
+        let mut world = World::new(self.seed, self.focus);
+        let mut game_loop = GameLoop::new(ManualClock::new(), self.dt);
+        let mut states = self.ticks.iter();
+        let mut remaining = self.ticks.len();
+        while remaining > 0 {
+            game_loop.clock().advance(self.dt);
+            // rounding in the clock can produce zero or two ticks
+            // for a step so pull input per tick rather than per step
+            game_loop.step(|dt| {
+                if let Some(state) = states.next() {
+                    world.update(state, dt as f32);
+                    remaining -= 1;
+                }
+            });
+        }
+        world
+    }
+
+    /// Replay the recording and check that the final world
+    /// matches the one that was recorded.
+    pub fn verify(&self) -> Result<World, ReplayError> {
+        let expected = try!(self.hash
+            .ok_or(ReplayError::Format("recording has no final `hash`".into())));
+        let world = self.replay();
+        let actual = world.hash();
+        if actual == expected {
+            Ok(world)
+        } else {
+            Err(ReplayError::Mismatch { expected: expected, actual: actual })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use na::Pnt2;
+
+    use input::InputState;
+    use world::World;
+    use super::{Recording, ReplayError};
+
+    /// Play `n` ticks of varied input and record them.
+    fn record(seed: u64, n: usize) -> Recording {
+        let mut recording = Recording::new(seed, 1.0 / 60.0, Pnt2::new(16.0, -8.0));
+        let mut world = World::new(recording.seed, recording.focus);
+        for i in 0..n {
+            let mut state = InputState::default();
+            state.axes.insert("horizontal".to_string(), if i % 3 == 0 { 1.0 } else { -1.0 });
+            state.axes.insert("vertical".to_string(), if i % 5 == 0 { 0.0 } else { 1.0 });
+            if i % 7 == 0 {
+                state.pressed.insert("activate".to_string());
+            }
+            world.update(&state, recording.dt as f32);
+            recording.push(state);
+        }
+        recording.finish(&world);
+        recording
+    }
+
+    fn round_trip(recording: &Recording, name: &str) -> Recording {
+        let path = env::temp_dir().join(name);
+        recording.save(&path).unwrap();
+        Recording::load(&path).unwrap()
+    }
+
+    #[test]
+    fn saved_recording_verifies() {
+        let recording = record(1234, 300);
+        let loaded = round_trip(&recording, "splore_replay_verifies.json");
+        assert_eq!(loaded.seed, recording.seed);
+        assert_eq!(loaded.ticks, recording.ticks);
+        assert_eq!(loaded.hash, recording.hash);
+        let world = loaded.verify().unwrap();
+        assert_eq!(world.ticks(), 300);
+    }
+
+    #[test]
+    fn tampered_recording_does_not_verify() {
+        let recording = record(1234, 300);
+
+        let mut input = round_trip(&recording, "splore_replay_tampered_input.json");
+        input.ticks[150].axes.insert("horizontal".to_string(), 0.0);
+        match input.verify() {
+            Err(ReplayError::Mismatch { .. }) => {},
+            other => panic!("expected a mismatch, got {:?}", other.map(|x| x.hash()))
+        }
+
+        let mut seed = round_trip(&recording, "splore_replay_tampered_seed.json");
+        seed.seed += 1;
+        match seed.verify() {
+            Err(ReplayError::Mismatch { .. }) => {},
+            other => panic!("expected a mismatch, got {:?}", other.map(|x| x.hash()))
+        }
+    }
+
+    #[test]
+    fn recording_with_bad_dt_does_not_load() {
+        for &dt in [0.0, -1.0 / 60.0].iter() {
+            let mut recording = record(1, 10);
+            recording.dt = dt;
+            let path = env::temp_dir().join("splore_replay_bad_dt.json");
+            recording.save(&path).unwrap();
+            match Recording::load(&path) {
+                Err(ReplayError::Format(_)) => {},
+                Err(err) => panic!("expected a format error for a dt of {}, got {}", dt, err),
+                Ok(_) => panic!("expected a format error for a dt of {}", dt)
+            }
+        }
+    }
+
+    #[test]
+    fn recording_without_hash_does_not_verify() {
+        let mut recording = record(1, 10);
+        recording.hash = None;
+        match recording.verify() {
+            Err(ReplayError::Format(_)) => {},
+            other => panic!("expected a format error, got {:?}", other.map(|x| x.hash()))
+        }
+    }
+}
@@ -1,7 +1,11 @@
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::default::Default;
+use std::fmt;
 use std::mem;
 use std::ops::Deref;
+use std::rc::Rc;
 
 use glium;
 use glium::{
@@ -18,25 +22,93 @@ use glium::buffer::BufferSlice;
 use glium::index::PrimitiveType;
 use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
 use na;
-use na::{Mat4, Pnt3};
+use na::{Mat3, Mat4, Pnt2, Pnt3, Vec2, Vec3};
 
 use textureatlas::{Frame, TextureAtlas};
 
+/// The vertex format for everything drawn by a Scene.
+///
+/// Quads are four vertices in the order bottom left,
+/// top left, top right, bottom right.
 #[derive(Copy, Clone, Debug)]
-struct Vertex {
-    position: [f32; 3],
-    texcoords: [f32; 2]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub texcoords: [f32; 2],
+    pub color: [f32; 4]
 }
 
-implement_vertex!(Vertex, position, texcoords);
+implement_vertex!(Vertex, position, texcoords, color);
+
+/// Where and how a Sprite's frame is placed in the world.
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    pub position: Pnt3<f32>,
+    /// Anti-clockwise rotation about the pivot in radians.
+    pub rotation: f32,
+    pub scale: Vec2<f32>,
+    /// The point that is placed at `position` and that the
+    /// sprite rotates and scales about, given as a fraction
+    /// of the frame size from its bottom left corner.
+    pub pivot: Vec2<f32>,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            position: Pnt3::new(0.0, 0.0, 0.0),
+            rotation: 0.0,
+            scale: Vec2::new(1.0, 1.0),
+            pivot: Vec2::new(0.0, 0.0),
+            flip_x: false,
+            flip_y: false,
+        }
+    }
+}
+
+impl Transform {
+    /// The 2D affine matrix for the translation, rotation
+    /// and scale of this Transform.
+    ///
+    /// Flipping is left out as it is applied to the texture
+    /// coordinates rather than the geometry.
+    pub fn to_mat(&self) -> Mat3<f32> {
+        let (sin, cos) = self.rotation.sin_cos();
+        Mat3::new(
+            cos * self.scale.x, -sin * self.scale.y, self.position.x,
+            sin * self.scale.x, cos * self.scale.y, self.position.y,
+            0.0, 0.0, 1.0)
+    }
+}
+
+/// Identifies one of the TextureAtlases registered with a Scene.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AtlasId(usize);
+
+/// A run of sprites, in draw order, that all use the same
+/// atlas page and so can be drawn with a single call.
+#[derive(Copy, Clone, Debug)]
+struct Batch {
+    atlas: AtlasId,
+    page: usize,
+    start: usize,
+    count: usize
+}
 
 pub enum Sprite {
     Static {
-        position: Pnt3<f32>,
+        transform: Transform,
+        color: [f32; 4],
+        atlas: AtlasId,
+        frame_name: String,
         frame: Frame,
     },
     Animated {
-        position: Pnt3<f32>,
+        transform: Transform,
+        color: [f32; 4],
+        atlas: AtlasId,
+        frame_names: Vec<String>,
         frames: Vec<Frame>,
         fps: f32,
         current_frame: usize,
@@ -55,44 +127,278 @@ impl Sprite {
         }
     }
 
+    /// The atlas that this sprite's frames come from.
     #[inline]
-    pub fn get_position(&self) -> &Pnt3<f32> {
+    pub fn get_atlas(&self) -> AtlasId {
+        match *self {
+            Sprite::Static { atlas, .. } => atlas,
+            Sprite::Animated { atlas, .. } => atlas
+        }
+    }
+
+    #[inline]
+    pub fn get_transform(&self) -> &Transform {
         match *self {
-            Sprite::Static { ref position, .. } => position,
-            Sprite::Animated { ref position, .. } => position
+            Sprite::Static { ref transform, .. } => transform,
+            Sprite::Animated { ref transform, .. } => transform
         }
     }
 
+    #[inline]
+    pub fn get_transform_mut(&mut self) -> &mut Transform {
+        match *self {
+            Sprite::Static { ref mut transform, .. } => transform,
+            Sprite::Animated { ref mut transform, .. } => transform
+        }
+    }
+
+    #[inline]
+    pub fn get_position(&self) -> &Pnt3<f32> {
+        &self.get_transform().position
+    }
+
     #[inline]
     pub fn set_position(&mut self, x: f32, y: f32) {
-        let mut position = match *self {
-            Sprite::Static { ref mut position, .. } => position,
-            Sprite::Animated { ref mut position, .. } => position,
-        };
+        let position = &mut self.get_transform_mut().position;
         position.x = x;
         position.y = y;
     }
 
     #[inline]
     pub fn set_position_z(&mut self, z: f32) {
-        let mut position = match *self {
-            Sprite::Static { ref mut position, .. } => position,
-            Sprite::Animated { ref mut position, .. } => position,
-        };
-        position.z = z;
+        self.get_transform_mut().position.z = z;
+    }
+
+    /// Set the anti-clockwise rotation in radians.
+    #[inline]
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.get_transform_mut().rotation = rotation;
+    }
+
+    #[inline]
+    pub fn set_scale(&mut self, x: f32, y: f32) {
+        self.get_transform_mut().scale = Vec2::new(x, y);
+    }
+
+    /// Mirror the frame horizontally and/or vertically in
+    /// place, so that it covers the same area as before and
+    /// stays put relative to the pivot.
+    ///
+    /// Attached children are mirrored along with it.
+    #[inline]
+    pub fn set_flip(&mut self, flip_x: bool, flip_y: bool) {
+        let transform = self.get_transform_mut();
+        transform.flip_x = flip_x;
+        transform.flip_y = flip_y;
+    }
+
+    /// Set the pivot as a fraction of the frame size,
+    /// e.g. `(0.5, 0.5)` for the centre.
+    #[inline]
+    pub fn set_pivot(&mut self, x: f32, y: f32) {
+        self.get_transform_mut().pivot = Vec2::new(x, y);
+    }
+
+    /// The RGBA tint that the frame is multiplied by.
+    #[inline]
+    pub fn get_color(&self) -> &[f32; 4] {
+        match *self {
+            Sprite::Static { ref color, .. } => color,
+            Sprite::Animated { ref color, .. } => color
+        }
+    }
+
+    #[inline]
+    fn get_color_mut(&mut self) -> &mut [f32; 4] {
+        match *self {
+            Sprite::Static { ref mut color, .. } => color,
+            Sprite::Animated { ref mut color, .. } => color
+        }
+    }
+
+    /// Set the RGB tint, leaving the opacity untouched.
+    #[inline]
+    pub fn set_color(&mut self, r: f32, g: f32, b: f32) {
+        let color = self.get_color_mut();
+        color[0] = r;
+        color[1] = g;
+        color[2] = b;
+    }
+
+    #[inline]
+    pub fn set_alpha(&mut self, a: f32) {
+        self.get_color_mut()[3] = a;
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    NoSuchSprite(String),
+    /// Attaching would make a sprite its own ancestor.
+    Cycle {
+        child: String,
+        parent: String
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SceneError::NoSuchSprite(ref name) => write!(f, "No sprite with name: `{}`", name),
+            SceneError::Cycle { ref child, ref parent } =>
+                write!(f, "Cannot attach `{}` to `{}` as it would create a cycle", child, parent),
+        }
     }
 }
 
 pub struct Scene<F> {
     capacity: usize,
-    texture: TextureAtlas,
+    atlases: Vec<Rc<TextureAtlas>>,
     sprites: HashMap<String, Sprite>,
+    /// Maps child sprite names to the name of their parent.
+    parents: HashMap<String, String>,
+    batches: Vec<Batch>,
     program: Program,
     vertex_buffer: VertexBuffer<Vertex>,
     index_buffer: IndexBuffer<u16>,
     display: F
 }
 
+/// Add the indices for the `i`th quad of a vertex buffer.
+fn push_quad_indices(indices: &mut Vec<u16>, i: usize) {
+    let index = (i * 4) as u16;
+    indices.push(index+1);
+    indices.push(index+2);
+    indices.push(index);
+
+    indices.push(index+2);
+    indices.push(index);
+    indices.push(index+3);
+}
+
+/// The corners of a sprite's quad relative to its pivot,
+/// before transforming, as `(x1, y1, x2, y2)`.
+///
+/// The pivot is relative to the untrimmed size of the frame
+/// and trimmed frames are placed where their pixels were in
+/// the untrimmed image, so trimming never moves a sprite.
+/// Flipping mirrors that placement within the untrimmed size
+/// to match the flipped texture coordinates.
+fn local_bounds(transform: &Transform, frame: &Frame) -> (f32, f32, f32, f32) {
+    // offsets are measured down from the top of the image
+    let left = if transform.flip_x {
+        frame.source_w - frame.x_offset - frame.w
+    } else {
+        frame.x_offset
+    };
+    let bottom = if transform.flip_y {
+        frame.y_offset
+    } else {
+        frame.source_h - frame.y_offset - frame.h
+    };
+    let x1 = left - transform.pivot.x * frame.source_w;
+    let y1 = bottom - transform.pivot.y * frame.source_h;
+    (x1, y1, x1 + frame.w, y1 + frame.h)
+}
+
+/// The mirroring done by a sprite's flip as a matrix, which
+/// is applied to its children so that they flip along with
+/// it, e.g. a sword held by a character turning to face left.
+///
+/// Like the flip of the sprite itself this mirrors about the
+/// centre of its untrimmed frame.
+fn flip_mat(transform: &Transform, frame: &Frame) -> Mat3<f32> {
+    let (sx, tx) = if transform.flip_x {
+        (-1.0, (1.0 - 2.0 * transform.pivot.x) * frame.source_w)
+    } else {
+        (1.0, 0.0)
+    };
+    let (sy, ty) = if transform.flip_y {
+        (-1.0, (1.0 - 2.0 * transform.pivot.y) * frame.source_h)
+    } else {
+        (1.0, 0.0)
+    };
+    Mat3::new(
+        sx, 0.0, tx,
+        0.0, sy, ty,
+        0.0, 0.0, 1.0)
+}
+
+/// The transform of a sprite in world space, combining it
+/// with the transforms and flips of all of its ancestors.
+///
+/// Results are stored in `cache` so that shared ancestors
+/// are only computed once.
+fn world_transform(
+    sprites: &HashMap<String, Sprite>,
+    parents: &HashMap<String, String>,
+    name: &str,
+    cache: &mut HashMap<String, (Mat3<f32>, f32)>) -> (Mat3<f32>, f32) {
+    if let Some(world) = cache.get(name) {
+        return *world;
+    }
+
+    let transform = sprites.get(name)
+        .expect(&format!("No sprite with name: `{}`", name))
+        .get_transform();
+    let local = (transform.to_mat(), transform.position.z);
+    let world = match parents.get(name) {
+        Some(parent) if sprites.contains_key(parent) => {
+            let (matrix, z) = world_transform(sprites, parents, parent, cache);
+            let parent = &sprites[parent];
+            let flip = flip_mat(parent.get_transform(), parent.get_current_frame());
+            (matrix * flip * local.0, z + local.1)
+        },
+        _ => local
+    };
+    cache.insert(name.to_string(), world);
+    world
+}
+
+/// Compare the `(z, (atlas, page), name)` of two sprites.
+///
+/// Sprites are ordered back to front by their world depth,
+/// with higher `z` drawn in front. Sprites at the same depth
+/// are grouped by atlas and page so that runs sharing a
+/// texture can be drawn together, and then ordered by name so
+/// that overlapping sprites don't swap places between frames.
+fn draw_order(a: (f32, (AtlasId, usize), &str), b: (f32, (AtlasId, usize), &str)) -> Ordering {
+    a.0.partial_cmp(&b.0)
+        .unwrap_or(Ordering::Equal)
+        .then(a.1.cmp(&b.1))
+        .then(a.2.cmp(b.2))
+}
+
+/// Check that `child` can be attached to `parent`, which
+/// both have to exist and mustn't leave the child as its own
+/// ancestor.
+fn check_attach(
+    sprites: &HashMap<String, Sprite>,
+    parents: &HashMap<String, String>,
+    child: &str,
+    parent: &str) -> Result<(), SceneError> {
+    if !sprites.contains_key(child) {
+        return Err(SceneError::NoSuchSprite(child.to_string()));
+    }
+    if !sprites.contains_key(parent) {
+        return Err(SceneError::NoSuchSprite(parent.to_string()));
+    }
+
+    // walk up from the new parent, if we reach the child
+    // then it would end up as its own ancestor
+    let mut ancestor = Some(parent);
+    while let Some(name) = ancestor {
+        if name == child {
+            return Err(SceneError::Cycle {
+                child: child.to_string(),
+                parent: parent.to_string()
+            });
+        }
+        ancestor = parents.get(name).map(|x| &x[..]);
+    }
+    Ok(())
+}
+
 // TODO: propagate error
 fn get_program<F>(display: &F) -> Program
     where F: Facade {
@@ -104,10 +410,13 @@ fn get_program<F>(display: &F) -> Program
                 uniform mat4 matrix;
                 in vec3 position;
                 in vec2 texcoords;
+                in vec4 color;
                 out vec2 v_texcoords;
+                out vec4 v_color;
                 void main() {
                     gl_Position = matrix * vec4(position, 1.0);
                     v_texcoords = texcoords;
+                    v_color = color;
                 }
             ",
 
@@ -115,9 +424,10 @@ fn get_program<F>(display: &F) -> Program
                 #version 140
                 uniform sampler2D tex;
                 in vec2 v_texcoords;
+                in vec4 v_color;
                 out vec4 f_color;
                 void main() {
-                    f_color = texture(tex, v_texcoords);
+                    f_color = texture(tex, v_texcoords) * v_color;
                 }
             "
         },
@@ -125,15 +435,18 @@ fn get_program<F>(display: &F) -> Program
 }
 
 impl<F: Facade + Clone> Scene<F> {
-    pub fn new(display: &F, texture: TextureAtlas) -> Scene<F> {
+    /// Create a Scene using `texture` as its default atlas.
+    pub fn new(display: &F, texture: Rc<TextureAtlas>) -> Scene<F> {
         Scene::with_capacity(display, texture, 50)
     }
 
-    pub fn with_capacity(display: &F, texture: TextureAtlas, n: usize) -> Scene<F> {
+    pub fn with_capacity(display: &F, texture: Rc<TextureAtlas>, n: usize) -> Scene<F> {
         Scene {
             capacity: n,
-            texture: texture,
+            atlases: vec![texture],
             sprites: HashMap::with_capacity(n),
+            parents: HashMap::new(),
+            batches: Vec::new(),
             program: get_program(display),
             vertex_buffer: VertexBuffer::empty_dynamic(display, 4 * n)
                 .ok().expect("Could not create VertexBuffer"),
@@ -148,7 +461,83 @@ impl<F: Facade + Clone> Scene<F> {
 
     }
 
-    /// Upload the data to the GPU for drawing
+    /// The atlas that `add_sprite` takes its frames from.
+    #[inline]
+    pub fn default_atlas(&self) -> AtlasId {
+        AtlasId(0)
+    }
+
+    /// Register another atlas that sprites can take frames from.
+    pub fn add_atlas(&mut self, atlas: Rc<TextureAtlas>) -> AtlasId {
+        self.atlases.push(atlas);
+        AtlasId(self.atlases.len() - 1)
+    }
+
+    #[inline]
+    pub fn get_atlas(&self, id: AtlasId) -> &TextureAtlas {
+        &self.atlases[id.0]
+    }
+
+    /// Swap `old` for `new` wherever it is registered, e.g.
+    /// after it has been reloaded by `Assets`.
+    ///
+    /// Sprites using it look their frames up again by name,
+    /// keeping their old frame if it has been removed.
+    pub fn reload_atlas(&mut self, old: &Rc<TextureAtlas>, new: &Rc<TextureAtlas>) {
+        for (i, atlas) in self.atlases.iter_mut().enumerate() {
+            if !Rc::ptr_eq(atlas, old) {
+                continue;
+            }
+            *atlas = new.clone();
+
+            for sprite in self.sprites.values_mut() {
+                match *sprite {
+                    Sprite::Static { atlas: id, ref frame_name, ref mut frame, .. } if id == AtlasId(i) => {
+                        if let Some(x) = new.get_frame(frame_name) {
+                            *frame = *x;
+                        }
+                    },
+                    Sprite::Animated { atlas: id, ref frame_names, ref mut frames, .. } if id == AtlasId(i) => {
+                        for (name, frame) in frame_names.iter().zip(frames.iter_mut()) {
+                            if let Some(x) = new.get_frame(name) {
+                                *frame = *x;
+                            }
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// The number of draw calls made by the last `draw`.
+    #[inline]
+    pub fn batch_count(&self) -> usize {
+        self.batches.len()
+    }
+
+    /// The corners of every sprite in world space, in the
+    /// same order as the vertices of its quad.
+    pub fn sprite_bounds(&self) -> Vec<[Pnt2<f32>; 4]> {
+        let mut world = HashMap::with_capacity(self.sprites.len());
+        self.sprites.iter().map(|(name, sprite)| {
+            let (matrix, _) = world_transform(&self.sprites, &self.parents, name, &mut world);
+            let (x1, y1, x2, y2) = local_bounds(sprite.get_transform(), sprite.get_current_frame());
+            let corner = |x: f32, y: f32| {
+                let p = matrix * Vec3::new(x, y, 1.0);
+                Pnt2::new(p.x, p.y)
+            };
+            [corner(x1, y1), corner(x1, y2), corner(x2, y2), corner(x2, y1)]
+        }).collect()
+    }
+
+    /// The number of sprites uploaded for the last `draw`.
+    pub fn sprite_count(&self) -> usize {
+        self.batches.iter().fold(0, |acc, x| acc + x.count)
+    }
+
+    /// Upload the data to the GPU for drawing, in the order
+    /// given by `draw_order`.
     fn upload_data(&mut self) {
         let vstride = mem::size_of::<Vertex>();
         let istride = mem::size_of::<u16>();
@@ -157,26 +546,49 @@ impl<F: Facade + Clone> Scene<F> {
 
         let mut vertices = Vec::with_capacity(voffset);
         let mut indices = Vec::with_capacity(ioffset);
-        for (i, sprite) in self.sprites.values().enumerate() {
-            let position = sprite.get_position();
+        let mut world = HashMap::with_capacity(self.sprites.len());
+        let mut order = Vec::with_capacity(self.sprites.len());
+        for (name, sprite) in self.sprites.iter() {
+            let (matrix, z) = world_transform(&self.sprites, &self.parents, name, &mut world);
+            let texture = (sprite.get_atlas(), sprite.get_current_frame().page);
+            order.push((texture, z, &name[..], matrix, sprite));
+        }
+        order.sort_by(|a, b| draw_order((a.1, a.0, a.2), (b.1, b.0, b.2)));
+
+        let mut batches: Vec<Batch> = Vec::new();
+        for (i, &((atlas, page), z, _, matrix, sprite)) in order.iter().enumerate() {
+            let same_texture = batches.last().map(|x| x.atlas == atlas && x.page == page).unwrap_or(false);
+            if same_texture {
+                batches.last_mut().unwrap().count += 1;
+            } else {
+                batches.push(Batch { atlas: atlas, page: page, start: i, count: 1 });
+            }
+
+            let transform = sprite.get_transform();
             let frame = sprite.get_current_frame();
-            let x1 = position.x;
-            let x2 = position.x + frame.w;
-            let y1 = position.y;
-            let y2 = position.y + frame.h;
-            vertices.push(Vertex { position: [x1, y1, position.z], texcoords: [frame.u1, frame.v1] });
-            vertices.push(Vertex { position: [x1, y2, position.z], texcoords: [frame.u1, frame.v2] });
-            vertices.push(Vertex { position: [x2, y2, position.z], texcoords: [frame.u2, frame.v2] });
-            vertices.push(Vertex { position: [x2, y1, position.z], texcoords: [frame.u2, frame.v1] });
-
-            let index = (i * 4) as u16;
-            indices.push(index+1);
-            indices.push(index+2);
-            indices.push(index);
-
-            indices.push(index+2);
-            indices.push(index);
-            indices.push(index+3);
+            let color = *sprite.get_color();
+
+            let (x1, y1, x2, y2) = local_bounds(transform, frame);
+            let corner = |x: f32, y: f32| {
+                let p = matrix * Vec3::new(x, y, 1.0);
+                [p.x, p.y, z]
+            };
+
+            let mut uvs = frame.texcoords();
+            if transform.flip_x {
+                uvs.swap(0, 3);
+                uvs.swap(1, 2);
+            }
+            if transform.flip_y {
+                uvs.swap(0, 1);
+                uvs.swap(2, 3);
+            }
+            vertices.push(Vertex { position: corner(x1, y1), texcoords: uvs[0], color: color });
+            vertices.push(Vertex { position: corner(x1, y2), texcoords: uvs[1], color: color });
+            vertices.push(Vertex { position: corner(x2, y2), texcoords: uvs[2], color: color });
+            vertices.push(Vertex { position: corner(x2, y1), texcoords: uvs[3], color: color });
+
+            push_quad_indices(&mut indices, i);
         }
 
         let mut vertex_slice = self.vertex_buffer
@@ -188,12 +600,78 @@ impl<F: Facade + Clone> Scene<F> {
 
         vertex_slice.write(&vertices);
         index_slice.write(&indices);
+
+        self.batches = batches;
     }
 
     pub fn draw<S: Surface>(&mut self, surface: &mut S, viewproj: &Mat4<f32>) {
         self.upload_data();
 
-        let sampled_texture = self.texture.texture.sampled()
+        let vertex_slice = self.vertex_buffer
+            .slice(0..self.sprites.len() * 4)
+            .expect("Could not take a slice of VertexBuffer");
+
+        let mut params = DrawParameters::default();
+        params.blend = Blend::alpha_blending();
+        params.depth = Depth {
+            test: glium::DepthTest::IfLessOrEqual,
+            write: true,
+            .. Default::default()
+        };
+
+        for batch in self.batches.iter() {
+            let sampled_texture = self.atlases[batch.atlas.0].texture(batch.page).sampled()
+                .minify_filter(MinifySamplerFilter::Nearest)
+                .magnify_filter(MagnifySamplerFilter::Nearest);
+            let uniforms = uniform! {
+                matrix: viewproj.clone(),
+                tex: sampled_texture
+            };
+
+            // indices refer to the whole vertex buffer so only
+            // the index buffer needs to be sliced per batch
+            let index_slice = self.index_buffer
+                .slice(batch.start * 6..(batch.start + batch.count) * 6)
+                .expect("Could not take a slice of IndexBuffer");
+
+            surface.draw(
+                vertex_slice,
+                index_slice,
+                &self.program,
+                &uniforms,
+                &params).unwrap();
+        }
+    }
+
+    /// Draw quads that were built outside of the Scene, such as
+    /// text from a BitmapFont, with the Scene's shaders and a
+    /// texture from one of its atlases.
+    ///
+    /// These aren't sorted with the Scene's sprites so rely on
+    /// the depth test to appear in front of or behind them.
+    /// Every quad must use frames from the given atlas page.
+    pub fn draw_quads<S>(
+        &self,
+        surface: &mut S,
+        viewproj: &Mat4<f32>,
+        atlas: AtlasId,
+        page: usize,
+        vertices: &[Vertex])
+        where S: Surface {
+        if vertices.is_empty() {
+            return;
+        }
+
+        let mut indices = Vec::with_capacity(vertices.len() / 4 * 6);
+        for i in 0..vertices.len() / 4 {
+            push_quad_indices(&mut indices, i);
+        }
+        let vertex_buffer = VertexBuffer::new(&self.display, vertices)
+            .ok().expect("Could not create VertexBuffer");
+        let index_buffer = IndexBuffer::new(&self.display, PrimitiveType::TrianglesList, &indices)
+            .ok().expect("Could not create IndexBuffer");
+
+        let sampled_texture = self.atlases[atlas.0].texture(page).sampled()
             .minify_filter(MinifySamplerFilter::Nearest)
             .magnify_filter(MagnifySamplerFilter::Nearest);
         let uniforms = uniform! {
@@ -201,13 +679,6 @@ impl<F: Facade + Clone> Scene<F> {
             tex: sampled_texture
         };
 
-        let vertex_slice = self.vertex_buffer
-            .slice(0..self.sprites.len() * 4)
-            .expect("Could not take a slice of VertexBuffer");
-        let index_slice = self.index_buffer
-            .slice(0..self.sprites.len() * 6)
-            .expect("Could not take a slice of IndexBuffer");
-
         let mut params = DrawParameters::default();
         params.blend = Blend::alpha_blending();
         params.depth = Depth {
@@ -216,13 +687,18 @@ impl<F: Facade + Clone> Scene<F> {
             .. Default::default()
         };
         surface.draw(
-            vertex_slice,
-            index_slice,
+            &vertex_buffer,
+            &index_buffer,
             &self.program,
             &uniforms,
             &params).unwrap();
     }
 
+    /// Find the id of an atlas registered with this Scene.
+    pub fn find_atlas(&self, atlas: &Rc<TextureAtlas>) -> Option<AtlasId> {
+        self.atlases.iter().position(|x| Rc::ptr_eq(x, atlas)).map(AtlasId)
+    }
+
     /// Extend the Vertex/Index buffers to double
     /// their current capacity.
     fn extend_buffers(&mut self) {
@@ -236,13 +712,25 @@ impl<F: Facade + Clone> Scene<F> {
 
     pub fn trim(&mut self) {}
 
-    /// Add a static Sprite to the Scene
+    /// Add a static Sprite to the Scene using a frame
+    /// from the default atlas.
     pub fn add_sprite(&mut self, name: &str, frame: &str) {
+        let atlas = self.default_atlas();
+        self.add_sprite_from(name, atlas, frame);
+    }
+
+    /// Add a static Sprite to the Scene using a frame
+    /// from the given atlas.
+    pub fn add_sprite_from(&mut self, name: &str, atlas: AtlasId, frame: &str) {
         {
-            let frame = self.texture.get_frame(frame)
+            let frame_name = frame.to_string();
+            let frame = self.atlases[atlas.0].get_frame(frame)
                 .expect(&format!("No frame with name: `{}`", frame));
             let sprite = Sprite::Static {
-                position : Pnt3::new(0.0, 0.0, 0.0),
+                transform : Transform::default(),
+                color : [1.0, 1.0, 1.0, 1.0],
+                atlas : atlas,
+                frame_name : frame_name,
                 frame : frame.clone(),
             };
             self.sprites.insert(name.to_string(), sprite);
@@ -263,6 +751,29 @@ impl<F: Facade + Clone> Scene<F> {
 
     }
 
+    /// Attach a sprite to a parent so that it is positioned,
+    /// rotated and scaled relative to it.
+    ///
+    /// A sprite can only have one parent so this replaces
+    /// any existing one.
+    pub fn attach(&mut self, child: &str, parent: &str) -> Result<(), SceneError> {
+        try!(check_attach(&self.sprites, &self.parents, child, parent));
+        self.parents.insert(child.to_string(), parent.to_string());
+        Ok(())
+    }
+
+    /// Detach a sprite from its parent, leaving it positioned
+    /// in world space by its own transform.
+    #[inline]
+    pub fn detach(&mut self, child: &str) {
+        self.parents.remove(child);
+    }
+
+    #[inline]
+    pub fn get_parent(&self, child: &str) -> Option<&str> {
+        self.parents.get(child).map(|x| &x[..])
+    }
+
     #[inline]
     pub fn get_sprite(&self, name: &str) -> Option<&Sprite> {
         self.sprites.get(name)
@@ -284,4 +795,168 @@ impl<F: Facade + Clone> Scene<F> {
     }
 
     // TODO: add iterator over all Sprites
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::f32::consts::PI;
+
+    use na::Vec3;
+
+    use textureatlas::Frame;
+    use super::{check_attach, draw_order, local_bounds, world_transform, AtlasId, SceneError, Sprite, Transform};
+
+    fn sprite(x: f32, y: f32, z: f32) -> Sprite {
+        let mut sprite = Sprite::Static {
+            transform: Transform::default(),
+            color: [1.0, 1.0, 1.0, 1.0],
+            atlas: AtlasId(0),
+            frame_name: "frame".to_string(),
+            frame: Frame::from_pixels(0.0, 0.0, 4.0, 2.0, 16.0, 16.0)
+        };
+        sprite.set_position(x, y);
+        sprite.set_position_z(z);
+        sprite
+    }
+
+    fn sprites(names: &[&str]) -> HashMap<String, Sprite> {
+        names.iter().map(|x| (x.to_string(), sprite(0.0, 0.0, 0.0))).collect()
+    }
+
+    fn parents(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(child, parent)| (child.to_string(), parent.to_string())).collect()
+    }
+
+    fn is_cycle(result: Result<(), SceneError>) -> bool {
+        match result {
+            Err(SceneError::Cycle { .. }) => true,
+            _ => false
+        }
+    }
+
+    /// Where a point of the named sprite ends up in the world.
+    fn world_point(sprites: &HashMap<String, Sprite>, parents: &HashMap<String, String>, name: &str, x: f32, y: f32) -> (f32, f32, f32) {
+        let (matrix, z) = world_transform(sprites, parents, name, &mut HashMap::new());
+        let p = matrix * Vec3::new(x, y, 1.0);
+        ((p.x * 1e4).round() / 1e4, (p.y * 1e4).round() / 1e4, z)
+    }
+
+    #[test]
+    fn attaching_to_itself_is_a_cycle() {
+        let sprites = sprites(&["a"]);
+        assert!(is_cycle(check_attach(&sprites, &HashMap::new(), "a", "a")));
+    }
+
+    #[test]
+    fn attaching_to_a_descendant_is_a_cycle() {
+        let sprites = sprites(&["a", "b", "c"]);
+        // b is attached to a, so a can't go under b
+        assert!(is_cycle(check_attach(&sprites, &parents(&[("b", "a")]), "a", "b")));
+        // nor under c, which is attached to b
+        assert!(is_cycle(check_attach(&sprites, &parents(&[("b", "a"), ("c", "b")]), "a", "c")));
+        assert!(check_attach(&sprites, &parents(&[("b", "a")]), "c", "b").is_ok());
+    }
+
+    #[test]
+    fn attaching_a_missing_sprite_fails() {
+        let sprites = sprites(&["a"]);
+        for &(child, parent) in &[("missing", "a"), ("a", "missing")] {
+            match check_attach(&sprites, &HashMap::new(), child, parent) {
+                Err(SceneError::NoSuchSprite(ref name)) => assert_eq!(name, "missing"),
+                _ => panic!("attaching `{}` to `{}` should fail", child, parent)
+            }
+        }
+    }
+
+    #[test]
+    fn children_move_with_their_parent() {
+        let mut sprites = HashMap::new();
+        let mut parent = sprite(10.0, 5.0, 1.0);
+        parent.set_rotation(PI / 2.0);
+        parent.set_scale(2.0, 2.0);
+        sprites.insert("parent".to_string(), parent);
+        sprites.insert("child".to_string(), sprite(1.0, 0.0, 0.5));
+        let parents = parents(&[("child", "parent")]);
+
+        assert_eq!(world_point(&sprites, &parents, "parent", 0.0, 0.0), (10.0, 5.0, 1.0));
+        // the child is 1 along the parent's x axis, which is
+        // turned to point up and doubled in length
+        assert_eq!(world_point(&sprites, &parents, "child", 0.0, 0.0), (10.0, 7.0, 1.5));
+        assert_eq!(world_point(&sprites, &parents, "child", 1.0, 0.0), (10.0, 9.0, 1.5));
+    }
+
+    #[test]
+    fn children_flip_with_their_parent() {
+        let mut sprites = HashMap::new();
+        let mut parent = sprite(10.0, 0.0, 0.0);
+        parent.set_flip(true, false);
+        sprites.insert("parent".to_string(), parent);
+        sprites.insert("child".to_string(), sprite(1.0, 0.0, 0.0));
+        let parents = parents(&[("child", "parent")]);
+
+        // mirrored about the middle of the parent's 4 pixel wide frame
+        assert_eq!(world_point(&sprites, &parents, "child", 0.0, 0.0), (13.0, 0.0, 0.0));
+        assert_eq!(world_point(&sprites, &parents, "child", 1.0, 0.0), (12.0, 0.0, 0.0));
+    }
+
+    /// A 4x2 frame trimmed from an 8x8 image, 1 pixel in from
+    /// the left and 2 down from the top.
+    fn trimmed_frame() -> Frame {
+        let mut frame = Frame::from_pixels(0.0, 0.0, 4.0, 2.0, 16.0, 16.0);
+        frame.x_offset = 1.0;
+        frame.y_offset = 2.0;
+        frame.source_w = 8.0;
+        frame.source_h = 8.0;
+        frame
+    }
+
+    #[test]
+    fn untrimmed_bounds_start_at_the_pivot() {
+        let frame = Frame::from_pixels(0.0, 0.0, 4.0, 2.0, 16.0, 16.0);
+        assert_eq!(local_bounds(&Transform::default(), &frame), (0.0, 0.0, 4.0, 2.0));
+    }
+
+    #[test]
+    fn trimmed_bounds_keep_their_place_in_the_image() {
+        // offsets are from the top, bounds from the bottom
+        assert_eq!(local_bounds(&Transform::default(), &trimmed_frame()), (1.0, 4.0, 5.0, 6.0));
+    }
+
+    #[test]
+    fn flipped_trimmed_bounds_are_mirrored_within_the_image() {
+        let frame = trimmed_frame();
+        let mut transform = Transform::default();
+        transform.flip_x = true;
+        assert_eq!(local_bounds(&transform, &frame), (3.0, 4.0, 7.0, 6.0));
+        transform.flip_x = false;
+        transform.flip_y = true;
+        assert_eq!(local_bounds(&transform, &frame), (1.0, 2.0, 5.0, 4.0));
+        transform.flip_x = true;
+        assert_eq!(local_bounds(&transform, &frame), (3.0, 2.0, 7.0, 4.0));
+    }
+
+    #[test]
+    fn pivot_is_a_fraction_of_the_untrimmed_size() {
+        let mut transform = Transform::default();
+        transform.pivot.x = 0.5;
+        transform.pivot.y = 0.25;
+        assert_eq!(local_bounds(&transform, &trimmed_frame()), (-3.0, 2.0, 1.0, 4.0));
+        transform.flip_x = true;
+        assert_eq!(local_bounds(&transform, &trimmed_frame()), (-1.0, 2.0, 3.0, 4.0));
+    }
+
+    #[test]
+    fn draw_order_is_by_depth_then_texture_then_name() {
+        let (a, b) = (AtlasId(0), AtlasId(1));
+        let sorted = vec![
+            (-1.0, (b, 0), "z"),
+            (0.0, (a, 0), "a"),
+            (0.0, (a, 0), "b"),
+            (0.0, (a, 1), "a"),
+            (0.0, (b, 0), "a"),
+            (2.0, (a, 0), "a")];
+        let mut order: Vec<_> = sorted.iter().rev().cloned().collect();
+        order.sort_by(|x, y| draw_order(*x, *y));
+        assert_eq!(order, sorted);
+    }
+}
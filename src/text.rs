@@ -0,0 +1,420 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use glium::Surface;
+use glium::backend::Facade;
+use glium_text;
+use glium_text::{FontTexture, TextDisplay, TextSystem};
+use na::{Mat4, Pnt3};
+
+pub type Color = (f32, f32, f32, f32);
+
+pub const WHITE: Color = (1.0, 1.0, 1.0, 1.0);
+
+/// Number of laid out strings kept by a TextRenderer before
+/// they are thrown away, so that text which changes every
+/// frame doesn't grow the cache forever.
+const LAYOUT_CACHE_SIZE: usize = 256;
+
+/// Convert a matrix into the column major array that
+/// glium_text expects.
+pub fn to_array(m: &Mat4<f32>) -> [[f32; 4]; 4] {
+    [
+        [m.m11, m.m21, m.m31, m.m41],
+        [m.m12, m.m22, m.m32, m.m42],
+        [m.m13, m.m23, m.m33, m.m43],
+        [m.m14, m.m24, m.m34, m.m44],
+    ]
+}
+
+/// A projection for drawing in screen space, where one unit
+/// is one pixel and the origin is the bottom left corner.
+pub fn screen_projection(width: f32, height: f32) -> Mat4<f32> {
+    Mat4::new(
+        2.0 / width, 0.0, 0.0, -1.0,
+        0.0, 2.0 / height, 0.0, -1.0,
+        0.0, 0.0, -1.0, 0.0,
+        0.0, 0.0, 0.0, 1.0)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right
+}
+
+/// How a string of text is laid out and coloured.
+#[derive(Copy, Clone, Debug)]
+pub struct TextStyle {
+    /// Height of a line of text in world units or pixels.
+    pub size: f32,
+    pub color: Color,
+    pub align: Align,
+    /// Width to wrap lines at, in the same units as `size`.
+    pub wrap: Option<f32>,
+    /// Distance between baselines as a multiple of `size`.
+    pub line_spacing: f32
+}
+
+impl Default for TextStyle {
+    fn default() -> TextStyle {
+        TextStyle {
+            size: 16.0,
+            color: WHITE,
+            align: Align::Left,
+            wrap: None,
+            line_spacing: 1.2
+        }
+    }
+}
+
+/// A TrueType font rasterised into a texture.
+#[derive(Clone)]
+pub struct Font {
+    texture: Rc<FontTexture>
+}
+
+impl Font {
+    /// Load a TTF font, rasterising its glyphs at `resolution`
+    /// pixels high. Text can be drawn at any size but will look
+    /// best close to this.
+    pub fn load<P, F>(display: &F, path: P, resolution: u32) -> io::Result<Font>
+        where P: AsRef<Path>,
+              F: Facade {
+        let file = try!(File::open(path));
+        let texture = try!(FontTexture::new(display, file, resolution)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Could not read font")));
+        Ok(Font {
+            texture: Rc::new(texture)
+        })
+    }
+}
+
+/// A piece of text with a single colour.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub color: Option<Color>
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    match value {
+        "white" => return Some((1.0, 1.0, 1.0, 1.0)),
+        "black" => return Some((0.0, 0.0, 0.0, 1.0)),
+        "red" => return Some((1.0, 0.0, 0.0, 1.0)),
+        "green" => return Some((0.0, 1.0, 0.0, 1.0)),
+        "blue" => return Some((0.0, 0.0, 1.0, 1.0)),
+        "yellow" => return Some((1.0, 1.0, 0.0, 1.0)),
+        _ => {}
+    }
+
+    let hex = value.trim_left_matches('#');
+    // checked up front so that slicing below stays on
+    // character boundaries
+    if !hex.chars().all(|c| c.is_digit(16)) {
+        return None;
+    }
+    if hex.len() != 6 && hex.len() != 8 {
+        return None;
+    }
+    let mut channels = [1.0; 4];
+    for i in 0..hex.len() / 2 {
+        let byte = match u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) {
+            Ok(byte) => byte,
+            Err(_) => return None
+        };
+        channels[i] = byte as f32 / 255.0;
+    }
+    Some((channels[0], channels[1], channels[2], channels[3]))
+}
+
+/// Split text with inline colour markup into spans.
+///
+/// Colours are set with `[color=red]...[/color]` or a hex
+/// value such as `[color=#ff8800]`, and may be nested. Tags
+/// that can't be parsed are left in the text as is.
+pub fn parse_markup(text: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut stack: Vec<Color> = Vec::new();
+    let mut current = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('[') {
+        let (before, tag) = rest.split_at(start);
+        current.push_str(before);
+        let end = match tag.find(']') {
+            Some(end) => end,
+            None => {
+                rest = tag;
+                break;
+            }
+        };
+
+        let name = &tag[1..end];
+        let color = if name.starts_with("color=") { parse_color(&name[6..]) } else { None };
+        let is_close = name == "/color" && !stack.is_empty();
+        if color.is_some() || is_close {
+            if !current.is_empty() {
+                spans.push(Span { text: current.clone(), color: stack.last().cloned() });
+                current.clear();
+            }
+            match color {
+                Some(color) => stack.push(color),
+                None => { stack.pop(); }
+            }
+        } else {
+            current.push_str(&tag[..end + 1]);
+        }
+        rest = &tag[end + 1..];
+    }
+
+    current.push_str(rest);
+    if !current.is_empty() {
+        spans.push(Span { text: current, color: stack.last().cloned() });
+    }
+    spans
+}
+
+/// A run of text on a laid out line, `x` is measured from
+/// the start of the line.
+struct Run {
+    text: String,
+    color: Option<Color>,
+    x: f32
+}
+
+struct Line {
+    runs: Vec<Run>,
+    width: f32
+}
+
+/// A laid out line with the TextDisplay for each of its
+/// runs, as `(x, color, display)`, ready to be drawn.
+struct DisplayLine {
+    runs: Vec<(f32, Option<Color>, TextDisplay<Rc<FontTexture>>)>,
+    width: f32
+}
+
+/// Everything that changes how a string is laid out.
+#[derive(PartialEq, Eq, Hash)]
+struct LayoutKey {
+    /// The font's texture, which is kept alive by the cached
+    /// TextDisplays so its address can't be reused.
+    font: usize,
+    text: String,
+    size: u32,
+    wrap: Option<u32>
+}
+
+/// Draws strings using glium_text, in either world space
+/// alongside sprites or screen space for UI.
+///
+/// Laid out text is cached so that drawing the same string
+/// every frame doesn't measure each word again.
+pub struct TextRenderer {
+    system: TextSystem,
+    layouts: RefCell<HashMap<LayoutKey, Rc<Vec<DisplayLine>>>>
+}
+
+impl TextRenderer {
+    pub fn new<F: Facade>(display: &F) -> TextRenderer {
+        TextRenderer {
+            system: TextSystem::new(display),
+            layouts: RefCell::new(HashMap::new())
+        }
+    }
+
+    /// The width of a string when drawn one unit high.
+    pub fn measure(&self, font: &Font, text: &str) -> f32 {
+        TextDisplay::new(&self.system, font.texture.clone(), text).get_width()
+    }
+
+    /// Break spans into lines, wrapping on spaces.
+    fn layout(&self, font: &Font, spans: &[Span], style: &TextStyle) -> Vec<Line> {
+        let mut lines = vec![Line { runs: Vec::new(), width: 0.0 }];
+        for span in spans.iter() {
+            for (i, paragraph) in span.text.split('\n').enumerate() {
+                if i > 0 {
+                    lines.push(Line { runs: Vec::new(), width: 0.0 });
+                }
+                for (j, word) in paragraph.split(' ').enumerate() {
+                    let mut line = lines.pop().unwrap();
+                    // spaces are kept with the word that follows them
+                    // so that they disappear when the line wraps
+                    let spaced = if j > 0 && line.width > 0.0 { format!(" {}", word) } else { word.to_string() };
+                    let width = self.measure(font, &spaced) * style.size;
+                    let overflows = match style.wrap {
+                        Some(wrap) => line.width > 0.0 && line.width + width > wrap,
+                        None => false
+                    };
+                    let (text, width) = if overflows {
+                        lines.push(line);
+                        line = Line { runs: Vec::new(), width: 0.0 };
+                        (word.to_string(), self.measure(font, word) * style.size)
+                    } else {
+                        (spaced, width)
+                    };
+
+                    let x = line.width;
+                    let extend = line.runs.last().map(|x| x.color == span.color).unwrap_or(false);
+                    if extend {
+                        line.runs.last_mut().unwrap().text.push_str(&text);
+                    } else {
+                        line.runs.push(Run { text: text, color: span.color, x: x });
+                    }
+                    line.width += width;
+                    lines.push(line);
+                }
+            }
+        }
+        lines
+    }
+
+    /// Lay out text with markup, reusing the last layout of the
+    /// same text in the same font, size and wrap width.
+    fn cached_layout(&self, font: &Font, text: &str, style: &TextStyle) -> Rc<Vec<DisplayLine>> {
+        let key = LayoutKey {
+            font: &*font.texture as *const FontTexture as usize,
+            text: text.to_string(),
+            size: style.size.to_bits(),
+            wrap: style.wrap.map(|x| x.to_bits())
+        };
+        if let Some(lines) = self.layouts.borrow().get(&key) {
+            return lines.clone();
+        }
+
+        let spans = parse_markup(text);
+        let lines: Vec<DisplayLine> = self.layout(font, &spans, style).into_iter().map(|line| {
+            DisplayLine {
+                runs: line.runs.into_iter().map(|run| {
+                    let display = TextDisplay::new(&self.system, font.texture.clone(), &run.text);
+                    (run.x, run.color, display)
+                }).collect(),
+                width: line.width
+            }
+        }).collect();
+        let lines = Rc::new(lines);
+
+        let mut layouts = self.layouts.borrow_mut();
+        if layouts.len() >= LAYOUT_CACHE_SIZE {
+            layouts.clear();
+        }
+        layouts.insert(key, lines.clone());
+        lines
+    }
+
+    /// Draw text with its first baseline starting at `position`,
+    /// which is adjusted according to the alignment.
+    ///
+    /// `matrix` transforms from the space `position` is in
+    /// to the screen, i.e. the camera's view projection for
+    /// world space text or `screen_projection` for UI.
+    pub fn draw<S>(
+        &self,
+        surface: &mut S,
+        font: &Font,
+        text: &str,
+        position: &Pnt3<f32>,
+        style: &TextStyle,
+        matrix: &Mat4<f32>)
+        where S: Surface {
+        let lines = self.cached_layout(font, text, style);
+        for (i, line) in lines.iter().enumerate() {
+            let offset = match style.align {
+                Align::Left => 0.0,
+                Align::Center => -line.width / 2.0,
+                Align::Right => -line.width,
+            };
+            let y = position.y - i as f32 * style.size * style.line_spacing;
+            for &(run_x, color, ref display) in line.runs.iter() {
+                let x = position.x + offset + run_x;
+                let model = Mat4::new(
+                    style.size, 0.0, 0.0, x,
+                    0.0, style.size, 0.0, y,
+                    0.0, 0.0, 1.0, position.z,
+                    0.0, 0.0, 0.0, 1.0);
+                glium_text::draw(
+                    display,
+                    &self.system,
+                    surface,
+                    to_array(&(*matrix * model)),
+                    color.unwrap_or(style.color));
+            }
+        }
+    }
+
+    /// Draw text in screen space, with `x` and `y` in pixels
+    /// from the bottom left corner of the surface.
+    pub fn draw_screen<S>(
+        &self,
+        surface: &mut S,
+        font: &Font,
+        text: &str,
+        x: f32,
+        y: f32,
+        style: &TextStyle)
+        where S: Surface {
+        let (width, height) = surface.get_dimensions();
+        let projection = screen_projection(width as f32, height as f32);
+        self.draw(surface, font, text, &Pnt3::new(x, y, 0.0), style, &projection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_color, parse_markup, Span};
+
+    fn span(text: &str, color: Option<(f32, f32, f32, f32)>) -> Span {
+        Span { text: text.to_string(), color: color }
+    }
+
+    #[test]
+    fn parses_named_and_hex_colors() {
+        assert_eq!(parse_color("red"), Some((1.0, 0.0, 0.0, 1.0)));
+        assert_eq!(parse_color("#ff0000"), Some((1.0, 0.0, 0.0, 1.0)));
+        assert_eq!(parse_color("00ff0000"), Some((0.0, 1.0, 0.0, 0.0)));
+        assert_eq!(parse_color("#FFFFFF"), Some((1.0, 1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn rejects_malformed_colors() {
+        assert_eq!(parse_color("purple"), None);
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(parse_color("#ff00001"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+        assert_eq!(parse_color("+f00000"), None);
+        // multibyte characters mustn't be sliced through
+        assert_eq!(parse_color("aébcd"), None);
+        assert_eq!(parse_color("aébcdef"), None);
+    }
+
+    #[test]
+    fn splits_text_into_colored_spans() {
+        let red = Some((1.0, 0.0, 0.0, 1.0));
+        let blue = Some((0.0, 0.0, 1.0, 1.0));
+        assert_eq!(parse_markup("plain"), vec![span("plain", None)]);
+        assert_eq!(
+            parse_markup("a [color=red]b [color=#0000ff]c[/color] d[/color] e"),
+            vec![span("a ", None), span("b ", red), span("c", blue), span(" d", red), span(" e", None)]);
+    }
+
+    #[test]
+    fn leaves_unknown_tags_as_text() {
+        assert_eq!(parse_markup("[b]bold[/b]"), vec![span("[b]bold[/b]", None)]);
+        assert_eq!(parse_markup("[color=aébcd]x"), vec![span("[color=aébcd]x", None)]);
+        // a close without an open is just text
+        assert_eq!(parse_markup("x[/color]"), vec![span("x[/color]", None)]);
+    }
+
+    #[test]
+    fn keeps_an_unclosed_bracket() {
+        let red = Some((1.0, 0.0, 0.0, 1.0));
+        assert_eq!(parse_markup("a [b"), vec![span("a [b", None)]);
+        assert_eq!(parse_markup("[color=red]a [b"), vec![span("a [b", red)]);
+    }
+}
@@ -1,17 +1,32 @@
 
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use glium::backend::Facade;
-use glium::texture::{CompressedSrgbTexture2d, Texture};
+use glium::texture::{CompressedSrgbTexture2d, Texture, TextureCreationError};
 use image;
 use serde_json;
 use serde_json::value::Value;
 
+use atlasfile;
+use atlasfile::Packed;
+
 /// A single frame of the TextureAtlas
 /// represented in texture space coordinates.
+///
+/// Frames packed with `--trim` have had their transparent
+/// edges removed, the offset and source size describe where
+/// the remaining pixels sat in the original image so that
+/// they can be drawn in the same place.
+///
+/// A rotated frame is stored turned 90 degrees clockwise in
+/// the texture. Its size is still that of the upright image,
+/// use `texcoords` to get UVs that turn it back.
 #[derive(Copy, Clone)]
 pub struct Frame {
     pub u1: f32,
@@ -20,6 +35,63 @@ pub struct Frame {
     pub v2: f32,
     pub w: f32, // width in pixels
     pub h: f32, // height in pixels
+    pub x_offset: f32, // pixels trimmed from the left
+    pub y_offset: f32, // pixels trimmed from the top
+    pub source_w: f32, // width in pixels before trimming
+    pub source_h: f32, // height in pixels before trimming
+    pub rotated: bool,
+    pub page: usize, // index of the texture holding the frame
+}
+
+impl Frame {
+    /// Create an untrimmed frame from its pixel rectangle
+    /// in a texture of the given size.
+    ///
+    /// Texture coordinates are inset by half a texel so that
+    /// the edges of a frame sample the centre of its outermost
+    /// pixels, never the frame next to it. Packing with
+    /// `--extrude` keeps what is sampled just outside correct.
+    pub fn from_pixels(x: f32, y: f32, w: f32, h: f32, width: f32, height: f32) -> Frame {
+        Frame {
+            u1: (x + 0.5) / width,
+            v1: (y + 0.5) / height,
+            u2: (x + w - 0.5) / width,
+            v2: (y + h - 0.5) / height,
+            w: w,
+            h: h,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            source_w: w,
+            source_h: h,
+            rotated: false,
+            page: 0
+        }
+    }
+
+    /// Texture coordinates for the corners of a quad showing
+    /// this frame upright, in the order bottom left, top left,
+    /// top right, bottom right.
+    pub fn texcoords(&self) -> [[f32; 2]; 4] {
+        if self.rotated {
+            // the image was turned clockwise with rows running
+            // down the page, as `pack` writes it, so the corner
+            // at (u1, v1) upright is now at (u2, v1) and so on
+            [[self.u2, self.v1], [self.u1, self.v1], [self.u1, self.v2], [self.u2, self.v2]]
+        } else {
+            [[self.u1, self.v1], [self.u1, self.v2], [self.u2, self.v2], [self.u2, self.v1]]
+        }
+    }
+}
+
+/// A sequence of frames written to the atlas by the `pack`
+/// tool from images with numbered names, e.g. `walk_0.png`
+/// to `walk_7.png`.
+#[derive(Clone)]
+pub struct Animation {
+    pub frame_names: Vec<String>,
+    pub frames: Vec<Frame>,
+    /// Frames per second if one was given when packing.
+    pub fps: Option<f32>
 }
 
 /// The TextureAtlas is a struct that encapsulates
@@ -32,61 +104,335 @@ pub struct Frame {
 /// many different textures. In this case a single
 /// large texture can contain all smaller textures
 /// that are used in a scene and only be bound once.
+///
+/// When the frames don't fit in one texture they are split
+/// over several pages, each frame says which page it is on.
 pub struct TextureAtlas {
-    pub texture: CompressedSrgbTexture2d,
-    frames: HashMap<String, Frame>
+    pub pages: Vec<Rc<CompressedSrgbTexture2d>>,
+    frames: HashMap<String, Frame>,
+    animations: HashMap<String, Animation>
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+    Io(io::Error),
+    Image(image::ImageError),
+    Texture(TextureCreationError),
+    Json(serde_json::Error),
+    /// The JSON did not have the expected shape.
+    Format(String)
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AtlasError::Io(ref err) => write!(f, "IO error: {}", err),
+            AtlasError::Image(ref err) => write!(f, "Image error: {}", err),
+            AtlasError::Texture(ref err) => write!(f, "Could not create texture: {:?}", err),
+            AtlasError::Json(ref err) => write!(f, "JSON error: {}", err),
+            AtlasError::Format(ref msg) => write!(f, "Invalid atlas: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for AtlasError {
+    fn from(err: io::Error) -> AtlasError {
+        AtlasError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for AtlasError {
+    fn from(err: image::ImageError) -> AtlasError {
+        AtlasError::Image(err)
+    }
+}
+
+impl From<TextureCreationError> for AtlasError {
+    fn from(err: TextureCreationError) -> AtlasError {
+        AtlasError::Texture(err)
+    }
+}
+
+impl From<serde_json::Error> for AtlasError {
+    fn from(err: serde_json::Error) -> AtlasError {
+        AtlasError::Json(err)
+    }
+}
+
+/// Load an image from disk straight into a texture.
+pub fn load_texture<P, F>(path: P, display: &F) -> Result<CompressedSrgbTexture2d, AtlasError>
+    where P: AsRef<Path>,
+          F: Facade {
+    let image = try!(image::open(path));
+    let texture = try!(CompressedSrgbTexture2d::new(display, image));
+    Ok(texture)
+}
+
+/// Build a frame from the area `[x, y, w, h]` it takes up on
+/// a page, which for a rotated frame has its width and height
+/// swapped.
+fn packed_frame(name: &str, rect: &[f32], rotated: bool, page: usize, sizes: &[(f32, f32)]) -> Result<Frame, AtlasError> {
+    let &(width, height) = try!(sizes.get(page)
+        .ok_or(AtlasError::Format(format!("frame `{}` is on missing page {}", name, page))));
+    let mut frame = Frame::from_pixels(rect[0], rect[1], rect[2], rect[3], width, height);
+    frame.page = page;
+    frame.rotated = rotated;
+    if rotated {
+        frame.w = rect[3];
+        frame.h = rect[2];
+        frame.source_w = frame.w;
+        frame.source_h = frame.h;
+    }
+    Ok(frame)
+}
+
+/// Build a frame from one read by `atlasfile`, whose page
+/// is one of those with the given sizes.
+fn to_frame(name: &str, packed: &Packed, sizes: &[(f32, f32)]) -> Result<Frame, AtlasError> {
+    let rect = [packed.x as f32, packed.y as f32, packed.w as f32, packed.h as f32];
+    let mut frame = try!(packed_frame(name, &rect, packed.rotated, packed.page, sizes));
+    frame.x_offset = packed.offset_x as f32;
+    frame.y_offset = packed.offset_y as f32;
+    frame.source_w = packed.source_w as f32;
+    frame.source_h = packed.source_h as f32;
+    Ok(frame)
+}
+
+/// Build the frames read by `atlasfile`, rejecting any name
+/// that is used twice.
+fn to_frames(packed: Vec<(String, Packed)>, sizes: &[(f32, f32)]) -> Result<HashMap<String, Frame>, AtlasError> {
+    let mut frames = HashMap::with_capacity(packed.len());
+    for (name, packed) in packed {
+        if frames.contains_key(&name) {
+            return Err(AtlasError::Format(format!("frame `{}` is in the atlas more than once", name)));
+        }
+        let frame = try!(to_frame(&name, &packed, sizes));
+        frames.insert(name, frame);
+    }
+    Ok(frames)
+}
+
+fn texture_sizes(pages: &[Rc<CompressedSrgbTexture2d>]) -> Result<Vec<(f32, f32)>, AtlasError> {
+    if pages.is_empty() {
+        return Err(AtlasError::Format("an atlas needs at least one page".into()));
+    }
+    Ok(pages.iter()
+        .map(|x| (x.get_width() as f32, x.get_height().unwrap() as f32))
+        .collect())
+}
+
+fn read_json<P: AsRef<Path>>(path: P) -> Result<Value, AtlasError> {
+    let mut jsonfile = try!(File::open(path));
+    let ref mut jsonstr = String::new();
+    try!(jsonfile.read_to_string(jsonstr));
+    Ok(try!(serde_json::from_str(jsonstr)))
+}
+
+/// The images used by an atlas written by the `pack` tool.
+///
+/// These are listed under `pages` relative to the JSON, or
+/// for older single page atlases are a PNG next to it with
+/// the same name.
+pub fn page_paths<P: AsRef<Path>>(json_path: P) -> Result<Vec<PathBuf>, AtlasError> {
+    let json_path = json_path.as_ref();
+    let data = try!(read_json(json_path));
+    atlasfile::page_paths(json_path, &data).map_err(AtlasError::Format)
+}
+
+fn read_text<P: AsRef<Path>>(path: P) -> Result<String, AtlasError> {
+    let mut file = try!(File::open(path));
+    let mut text = String::new();
+    try!(file.read_to_string(&mut text));
+    Ok(text)
+}
+
+/// The images used by a CSV atlas written by the `pack`
+/// tool, which are listed relative to it on its first line.
+pub fn csv_page_paths<P: AsRef<Path>>(csv_path: P) -> Result<Vec<PathBuf>, AtlasError> {
+    let csv_path = csv_path.as_ref();
+    let (pages, _) = try!(atlasfile::parse_csv(&try!(read_text(csv_path))).map_err(AtlasError::Format));
+    if pages.is_empty() {
+        return Err(AtlasError::Format("missing `pages` line".into()));
+    }
+    let dir = csv_path.parent().unwrap_or(Path::new(""));
+    Ok(pages.iter().map(|x| dir.join(x)).collect())
+}
+
+/// Parse an animation from the `animations` section:
+///
+/// ```json
+/// "walk": { "frames": ["walk_0", "walk_1"], "fps": 12 }
+/// ```
+fn parse_animation(name: &str, value: &Value, frames: &HashMap<String, Frame>) -> Result<Animation, AtlasError> {
+    let names = try!(value.find("frames")
+        .and_then(|x| x.as_array())
+        .ok_or(AtlasError::Format(format!("animation `{}` should have a `frames` array", name))));
+    let mut animation = Animation {
+        frame_names: Vec::with_capacity(names.len()),
+        frames: Vec::with_capacity(names.len()),
+        fps: None
+    };
+    for frame_name in names.iter() {
+        let frame_name = try!(frame_name.as_string()
+            .ok_or(AtlasError::Format(format!("frames of animation `{}` should be names", name))));
+        let frame = try!(frames.get(frame_name)
+            .ok_or(AtlasError::Format(format!("animation `{}` uses missing frame `{}`", name, frame_name))));
+        animation.frame_names.push(frame_name.to_string());
+        animation.frames.push(*frame);
+    }
+    if let Some(fps) = value.find("fps") {
+        animation.fps = Some(try!(fps.as_f64()
+            .ok_or(AtlasError::Format(format!("`fps` of animation `{}` should be a number", name)))) as f32);
+    }
+    Ok(animation)
 }
 
 impl TextureAtlas {
     pub fn new( 
-        texture: CompressedSrgbTexture2d,
+        texture: Rc<CompressedSrgbTexture2d>,
+        frames: HashMap<String, Frame>) -> TextureAtlas {
+        TextureAtlas::with_pages(vec![texture], frames)
+    }
+
+    pub fn with_pages(
+        pages: Vec<Rc<CompressedSrgbTexture2d>>,
         frames: HashMap<String, Frame>) -> TextureAtlas {
 
         TextureAtlas {
-            texture: texture,
-            frames : frames
+            pages: pages,
+            frames : frames,
+            animations: HashMap::new()
         }
     }
 
-    /// TODO: make this use an asset store of some kind
-    /// so that we don't have to load the image in.
+    /// Load a TextureAtlas that was written by the `pack` tool,
+    /// panicking if anything goes wrong.
+    ///
+    /// Use `Assets` to load atlases that share textures or
+    /// where load errors need to be handled.
     pub fn from_packed<T, F>(
         image_path: T, 
         json_path: T, 
         display: &F) -> TextureAtlas
         where T: AsRef<Path>,
               F: Facade {
-        let image = image::open(image_path).unwrap();
-        let texture = CompressedSrgbTexture2d::new(display, image).unwrap();
+        let texture = load_texture(image_path, display).unwrap();
+        TextureAtlas::from_json(Rc::new(texture), json_path).unwrap()
+    }
+
+    /// Load a TextureAtlas and every page it lists from the
+    /// JSON written by the `pack` tool.
+    pub fn load<P, F>(json_path: P, display: &F) -> Result<TextureAtlas, AtlasError>
+        where P: AsRef<Path>,
+              F: Facade {
+        let mut pages = Vec::new();
+        for path in try!(page_paths(&json_path)) {
+            pages.push(Rc::new(try!(load_texture(path, display))));
+        }
+        TextureAtlas::from_json_pages(pages, json_path)
+    }
+
+    /// Create a TextureAtlas from an already loaded texture and
+    /// the path to the JSON describing its frames.
+    pub fn from_json<T>(
+        texture: Rc<CompressedSrgbTexture2d>,
+        json_path: T) -> Result<TextureAtlas, AtlasError>
+        where T: AsRef<Path> {
+        TextureAtlas::from_json_pages(vec![texture], json_path)
+    }
 
-        let mut jsonfile = File::open(json_path).unwrap();
-        let ref mut jsonstr = String::new();
-        jsonfile.read_to_string(jsonstr);
-        let data: Value = serde_json::from_str(jsonstr).unwrap();
+    /// Create a TextureAtlas from already loaded textures, in
+    /// the order given by `page_paths`, and the path to the
+    /// JSON describing their frames.
+    pub fn from_json_pages<T>(
+        pages: Vec<Rc<CompressedSrgbTexture2d>>,
+        json_path: T) -> Result<TextureAtlas, AtlasError>
+        where T: AsRef<Path> {
+        let data = try!(read_json(json_path));
 
-        let frames = data.find("frames")
-            .unwrap()
-            .as_object()
-            .unwrap();
+        let frames = try!(data.find("frames")
+            .and_then(|x| x.as_object())
+            .ok_or(AtlasError::Format("missing `frames` object".into())));
 
+        let sizes = try!(texture_sizes(&pages));
         let mut tiles = HashMap::new();
         for (name, frame) in frames.iter() {
-            let frame = frame.as_array().unwrap();
-            let x = frame[0].as_f64().unwrap();
-            let y = frame[1].as_f64().unwrap();
-            let w = frame[2].as_f64().unwrap();
-            let h = frame[3].as_f64().unwrap();
-            let frame = Frame {
-                u1: x as f32 / texture.get_width() as f32,
-                v1: y as f32 / texture.get_height().unwrap() as f32,
-                u2: ((x + w) / texture.get_width() as f64) as f32,
-                v2: ((y + h) / texture.get_height().unwrap() as f64) as f32,
-                w: w as f32,
-                h: h as f32
-            };
-            tiles.insert(name.clone(), frame);
+            let packed = try!(atlasfile::parse_frame(name, frame).map_err(AtlasError::Format));
+            tiles.insert(name.clone(), try!(to_frame(name, &packed, &sizes)));
         }
-        TextureAtlas::new(texture, tiles)
+
+        let mut animations = HashMap::new();
+        if let Some(section) = data.find("animations") {
+            let section = try!(section.as_object()
+                .ok_or(AtlasError::Format("`animations` should be an object".into())));
+            for (name, animation) in section.iter() {
+                animations.insert(name.clone(), try!(parse_animation(name, animation, &tiles)));
+            }
+        }
+
+        let mut atlas = TextureAtlas::with_pages(pages, tiles);
+        atlas.animations = animations;
+        Ok(atlas)
+    }
+
+    /// Create a TextureAtlas from TexturePacker's JSON, in
+    /// either its hash or array format.
+    pub fn from_texturepacker<T>(
+        texture: Rc<CompressedSrgbTexture2d>,
+        json_path: T) -> Result<TextureAtlas, AtlasError>
+        where T: AsRef<Path> {
+        TextureAtlas::from_texturepacker_pages(vec![(texture, json_path)])
+    }
+
+    /// Create a TextureAtlas from several of TexturePacker's
+    /// JSON files, each describing the frames on one page, such
+    /// as the `_0.json`, `_1.json`, ... written by `pack` when
+    /// its frames don't fit on one page.
+    pub fn from_texturepacker_pages<T>(
+        pages: Vec<(Rc<CompressedSrgbTexture2d>, T)>) -> Result<TextureAtlas, AtlasError>
+        where T: AsRef<Path> {
+        let textures: Vec<_> = pages.iter().map(|x| x.0.clone()).collect();
+        let sizes = try!(texture_sizes(&textures));
+
+        let mut packed = Vec::new();
+        for (page, &(_, ref json_path)) in pages.iter().enumerate() {
+            let data = try!(read_json(json_path));
+            packed.extend(try!(atlasfile::parse_texturepacker(&data, page).map_err(AtlasError::Format)));
+        }
+        let frames = try!(to_frames(packed, &sizes));
+        Ok(TextureAtlas::with_pages(textures, frames))
+    }
+
+    /// Load a TextureAtlas and its pages from a libGDX
+    /// `.atlas` file, see `atlasfile::parse_libgdx`.
+    pub fn from_libgdx<P, F>(atlas_path: P, display: &F) -> Result<TextureAtlas, AtlasError>
+        where P: AsRef<Path>,
+              F: Facade {
+        let atlas_path = atlas_path.as_ref();
+        let (names, packed) = try!(atlasfile::parse_libgdx(&try!(read_text(atlas_path)))
+            .map_err(AtlasError::Format));
+        let dir = atlas_path.parent().unwrap_or(Path::new(""));
+        let mut pages = Vec::with_capacity(names.len());
+        for name in names {
+            pages.push(Rc::new(try!(load_texture(dir.join(name), display))));
+        }
+        let sizes = try!(texture_sizes(&pages));
+        let frames = try!(to_frames(packed, &sizes));
+        Ok(TextureAtlas::with_pages(pages, frames))
+    }
+
+    /// Create a TextureAtlas from the CSV written by the
+    /// `pack` tool, see `atlasfile::parse_csv`. The pages
+    /// should be in the order given by `csv_page_paths`.
+    pub fn from_csv<T>(
+        pages: Vec<Rc<CompressedSrgbTexture2d>>,
+        csv_path: T) -> Result<TextureAtlas, AtlasError>
+        where T: AsRef<Path> {
+        let (_, packed) = try!(atlasfile::parse_csv(&try!(read_text(csv_path)))
+            .map_err(AtlasError::Format));
+        let sizes = try!(texture_sizes(&pages));
+        let frames = try!(to_frames(packed, &sizes));
+        Ok(TextureAtlas::with_pages(pages, frames))
     }
 
     /// Create a TextureAtlas from a collection of images.
@@ -100,7 +446,62 @@ impl TextureAtlas {
 
     // }
 
+    /// The texture holding the given page, panicking if
+    /// there is no such page.
+    #[inline]
+    pub fn texture(&self, page: usize) -> &CompressedSrgbTexture2d {
+        &self.pages[page]
+    }
+
     pub fn get_frame(&self, name: &str) -> Option<&Frame> {
         self.frames.get(name)
     }
+
+    /// The frames of an animation in the order they play.
+    pub fn get_animation(&self, name: &str) -> Option<&Animation> {
+        self.animations.get(name)
+    }
+
+    /// Iterate over every frame and its name.
+    pub fn frames(&self) -> ::std::collections::hash_map::Iter<String, Frame> {
+        self.frames.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{packed_frame, Frame};
+
+    /// The texel of a `width` by `height` page that a texture
+    /// coordinate samples.
+    fn texel(uv: [f32; 2], width: f32, height: f32) -> (u32, u32) {
+        ((uv[0] * width) as u32, (uv[1] * height) as u32)
+    }
+
+    #[test]
+    fn rotated_frames_sample_the_same_corners() {
+        // a 3x2 image labelled by its own pixel positions, put
+        // on a page upright at (1, 1) and turned clockwise at
+        // (8, 4) the way texture_packer turns it
+        let (w, h) = (3, 2);
+        let mut page = HashMap::new();
+        for y in 0..h {
+            for x in 0..w {
+                page.insert((1 + x, 1 + y), (x, y));
+                page.insert((8 + h - 1 - y, 4 + x), (x, y));
+            }
+        }
+
+        let sizes = [(16.0, 16.0)];
+        let upright = Frame::from_pixels(1.0, 1.0, w as f32, h as f32, 16.0, 16.0);
+        let rotated = packed_frame("rotated", &[8.0, 4.0, h as f32, w as f32], true, 0, &sizes).unwrap();
+        assert_eq!((rotated.w, rotated.h), (w as f32, h as f32));
+
+        let upright_corners: Vec<_> = upright.texcoords().iter().map(|&x| page[&texel(x, 16.0, 16.0)]).collect();
+        let rotated_corners: Vec<_> = rotated.texcoords().iter().map(|&x| page[&texel(x, 16.0, 16.0)]).collect();
+        assert_eq!(upright_corners, vec![(0, 0), (0, h - 1), (w - 1, h - 1), (w - 1, 0)]);
+        assert_eq!(rotated_corners, upright_corners);
+    }
 }
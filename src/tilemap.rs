@@ -1,4 +1,11 @@
 
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
 use glium;
 use glium::{
     Blend,
@@ -13,6 +20,8 @@ use glium::backend::Facade;
 use glium::index::PrimitiveType;
 use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
 use na::{Mat4};
+use serde_json;
+use serde_json::value::Value;
 
 use textureatlas::TextureAtlas;
 
@@ -20,10 +29,17 @@ pub struct TileMap<T>
     where T: Default + Tile {
     pub width: usize,
     pub height: usize,
+    pub tile_width: u16,
+    pub tile_height: u16,
     tiles: Vec<T>,
-    pub atlas: TextureAtlas,
+    pub atlas: Rc<TextureAtlas>,
     pub vertex_buffer: VertexBuffer<Vertex>,
-    pub index_buffer: IndexBuffer<u16>,
+    /// Indices of the tiles on each page of the atlas.
+    pub index_buffers: Vec<IndexBuffer<u16>>,
+    /// A reloaded atlas that was missing frames for some of
+    /// the tiles, so that when it is reloaded again it is
+    /// still recognised as the one to replace.
+    rejected_atlas: Option<Rc<TextureAtlas>>,
     program: Program
 }
 
@@ -31,10 +47,174 @@ pub trait Tile {
     fn name<'a>(&'a self) -> &'a str;
 }
 
+/// Tiles loaded from a map file are just frame names.
+impl Tile for String {
+    fn name<'a>(&'a self) -> &'a str {
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The map file did not have the expected shape.
+    Format(String),
+    /// A tile names a frame that isn't in the atlas.
+    MissingFrame(String)
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapError::Io(ref err) => write!(f, "IO error: {}", err),
+            MapError::Json(ref err) => write!(f, "JSON error: {}", err),
+            MapError::Format(ref msg) => write!(f, "Invalid map: {}", msg),
+            MapError::MissingFrame(ref name) => write!(f, "No frame with name: `{}`", name),
+        }
+    }
+}
+
+impl From<io::Error> for MapError {
+    fn from(err: io::Error) -> MapError {
+        MapError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for MapError {
+    fn from(err: serde_json::Error) -> MapError {
+        MapError::Json(err)
+    }
+}
+
+/// The contents of a map file, which describes a TileMap
+/// as a grid of frame names from a single atlas.
+///
+/// ```json
+/// {
+///     "atlas": "overground.json",
+///     "width": 2, "height": 1,
+///     "tile_width": 16, "tile_height": 16,
+///     "tiles": ["grass", "dirt"]
+/// }
+/// ```
+///
+/// `atlas` is relative to the map file.
+#[derive(Clone, Debug)]
+pub struct MapData {
+    pub atlas: PathBuf,
+    pub width: usize,
+    pub height: usize,
+    pub tile_width: u16,
+    pub tile_height: u16,
+    pub tiles: Vec<String>
+}
+
+impl MapData {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MapData, MapError> {
+        let path = path.as_ref();
+        let mut file = try!(File::open(path));
+        let mut json = String::new();
+        try!(file.read_to_string(&mut json));
+        let data: Value = try!(serde_json::from_str(&json));
+
+        let atlas = try!(data.find("atlas").and_then(|x| x.as_string())
+            .ok_or(MapError::Format("missing `atlas`".into())));
+        let atlas = path.parent().unwrap_or(Path::new("")).join(atlas);
+        let number = |key: &str| {
+            data.find(key).and_then(|x| x.as_u64())
+                .ok_or(MapError::Format(format!("missing `{}`", key)))
+        };
+        let width = try!(number("width")) as usize;
+        let height = try!(number("height")) as usize;
+        let tile_width = try!(number("tile_width")) as u16;
+        let tile_height = try!(number("tile_height")) as u16;
+
+        let names = try!(data.find("tiles").and_then(|x| x.as_array())
+            .ok_or(MapError::Format("missing `tiles`".into())));
+        if names.len() != width * height {
+            return Err(MapError::Format(format!(
+                "expected {} tiles for a {}x{} map, found {}",
+                width * height, width, height, names.len())));
+        }
+        let mut tiles = Vec::with_capacity(names.len());
+        for name in names.iter() {
+            let name = try!(name.as_string()
+                .ok_or(MapError::Format("tile names must be strings".into())));
+            tiles.push(name.to_string());
+        }
+
+        Ok(MapData {
+            atlas: atlas,
+            width: width,
+            height: height,
+            tile_width: tile_width,
+            tile_height: tile_height,
+            tiles: tiles
+        })
+    }
+}
+
 fn get_index(x: u16, y: u16, width: u16) -> u16 {
     (x + y * width) * 3 + x + y * width
 }
 
+/// Build the geometry for a grid of tiles with texture
+/// coordinates taken from their frames in `atlas`, with
+/// an index buffer for each page of the atlas.
+fn build_buffers<F, T>(
+    display: &F,
+    width: usize,
+    height: usize,
+    tile_width: u16,
+    tile_height: u16,
+    tiles: &[T],
+    atlas: &TextureAtlas) -> Result<(VertexBuffer<Vertex>, Vec<IndexBuffer<u16>>), MapError>
+    where F: Facade,
+          T: Tile {
+    let mut vertices = Vec::with_capacity(width * height * 4);
+    let mut indices = vec![Vec::new(); atlas.pages.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let tile_index = width * y + x;
+            let tile = tiles.get(tile_index)
+                .expect(&format!("No tile found at index `{}`", tile_index));
+            let name = tile.name();
+            let frame = try!(atlas.get_frame(name)
+                .ok_or(MapError::MissingFrame(name.to_string())));
+            let x1 = x as f32 * tile_width as f32;
+            let x2 = x1 + tile_width as f32;
+            let y1 = y as f32 * tile_height as f32;
+            let y2 = y1 + tile_height as f32;
+            let uvs = frame.texcoords();
+            vertices.push(Vertex { position: [x1, y1], texcoords: uvs[0] });
+            vertices.push(Vertex { position: [x1, y2], texcoords: uvs[1] });
+            vertices.push(Vertex { position: [x2, y2], texcoords: uvs[2] });
+            vertices.push(Vertex { position: [x2, y1], texcoords: uvs[3] });
+            let index = get_index(x as u16, y as u16, width as u16);
+            let indices = &mut indices[frame.page];
+            // first triangle
+            indices.push(index + 1);
+            indices.push(index + 2);
+            indices.push(index);
+
+            // second triangle
+            indices.push(index + 2);
+            indices.push(index);
+            indices.push(index + 3);
+        }
+    }
+
+    let vertex_buffer = VertexBuffer::new(display, &vertices)
+        .ok().expect("Could not create TileMap VertexBuffer");
+    let index_buffers = indices.iter().map(|x| {
+        IndexBuffer::new(display, PrimitiveType::TrianglesList, x)
+            .ok().expect("Could not create TileMap IndexBuffer")
+    }).collect();
+
+    Ok((vertex_buffer, index_buffers))
+}
+
 // TODO: propagate error
 fn get_program<F>(display: &F) -> Program
     where F: Facade {
@@ -112,6 +292,42 @@ fn get_program<F>(display: &F) -> Program
     ).unwrap()
 }
 
+impl TileMap<String> {
+    /// Create a TileMap from a loaded map file.
+    pub fn from_data<F>(display: &F, data: &MapData, atlas: Rc<TextureAtlas>) -> TileMap<String>
+        where F: Facade {
+        TileMap::new(
+            display,
+            data.width, data.height,
+            data.tile_width, data.tile_height,
+            data.tiles.clone(),
+            atlas)
+    }
+
+    /// Replace the layout, tiles and atlas with those from a
+    /// map file, e.g. after it has been reloaded by `Assets`.
+    ///
+    /// If a tile's frame is missing from the atlas the TileMap
+    /// is left as it was.
+    pub fn set_data<F>(&mut self, display: &F, data: &MapData, atlas: Rc<TextureAtlas>) -> Result<(), MapError>
+        where F: Facade {
+        let (vertex_buffer, index_buffers) = try!(build_buffers(
+            display, data.width, data.height,
+            data.tile_width, data.tile_height,
+            &data.tiles, &atlas));
+        self.width = data.width;
+        self.height = data.height;
+        self.tile_width = data.tile_width;
+        self.tile_height = data.tile_height;
+        self.tiles = data.tiles.clone();
+        self.atlas = atlas;
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffers = index_buffers;
+        self.rejected_atlas = None;
+        Ok(())
+    }
+}
+
 impl<T: Default + Tile> TileMap<T> {
     // TODO: return Result<TileMap<T>> so we can propagate construction errors upwards
     // TODO: have TileMap handle its own drawing so that it can own a program and associated
@@ -123,63 +339,96 @@ impl<T: Default + Tile> TileMap<T> {
         tile_width: u16,
         tile_height: u16,
         tiles: Vec<T>,
-        atlas: TextureAtlas) -> TileMap<T>
+        atlas: Rc<TextureAtlas>) -> TileMap<T>
         where F: Facade {
 
         assert!(width * height == tiles.len(), "width * height does not equal length of tiles Vec");
 
-        let mut vertices = Vec::with_capacity(width * height * 4);
-        let mut indices = Vec::with_capacity(width * height * 6);
-        for y in 0..height {
-            for x in 0..width {
-                let tile_index = width * y + x;
-                let tile = tiles.get(tile_index)
-                    .expect(&format!("No tile found at index `{}`", tile_index));
-                let name = tile.name();
-                let frame = atlas.get_frame(name)
-                    .expect(&format!("Could not get frame from atlas with name `{}`", name));
-                let x1 = x as f32 * tile_width as f32;
-                let x2 = x1 + tile_width as f32;
-                let y1 = y as f32 * tile_height as f32;
-                let y2 = y1 + tile_height as f32;
-                vertices.push(Vertex { position: [x1, y1], texcoords: [frame.u1, frame.v1] });
-                vertices.push(Vertex { position: [x1, y2], texcoords: [frame.u1, frame.v2] });
-                vertices.push(Vertex { position: [x2, y2], texcoords: [frame.u2, frame.v2] });
-                vertices.push(Vertex { position: [x2, y1], texcoords: [frame.u2, frame.v1] });
-                let index = get_index(x as u16, y as u16, width as u16);
-                // first triangle
-                indices.push(index + 1);
-                indices.push(index + 2);
-                indices.push(index);
-
-                // second triangle
-                indices.push(index + 2);
-                indices.push(index);
-                indices.push(index + 3);
-            }
-        }
-
-        let vertex_buffer = VertexBuffer::new(display, &vertices)
-            .ok().expect("Could not create TileMap VertexBuffer");
-        let index_buffer = IndexBuffer::new(display, PrimitiveType::TrianglesList, &indices)
-            .ok().expect("Could not create TileMap IndexBuffer");
+        let (vertex_buffer, index_buffers) = build_buffers(
+            display, width, height, tile_width, tile_height, &tiles, &atlas)
+            .unwrap_or_else(|err| panic!("{}", err));
 
         TileMap {
             width : width,
             height : height,
+            tile_width : tile_width,
+            tile_height : tile_height,
             tiles : tiles,
             atlas : atlas,
             vertex_buffer : vertex_buffer,
-            index_buffer : index_buffer,
+            index_buffers : index_buffers,
+            rejected_atlas : None,
             program : get_program(display)
         }
     }
 
+    /// Swap the atlas that tiles take their frames from,
+    /// rebuilding the geometry to match.
+    ///
+    /// If a tile's frame is missing from `atlas` the old atlas
+    /// and geometry are kept.
+    pub fn set_atlas<F>(&mut self, display: &F, atlas: Rc<TextureAtlas>) -> Result<(), MapError>
+        where F: Facade {
+        let (vertex_buffer, index_buffers) = try!(build_buffers(
+            display, self.width, self.height,
+            self.tile_width, self.tile_height,
+            &self.tiles, &atlas));
+        self.atlas = atlas;
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffers = index_buffers;
+        self.rejected_atlas = None;
+        Ok(())
+    }
+
+    /// Swap `old` for `new` if it is the atlas in use, e.g.
+    /// after it has been reloaded by `Assets`.
+    ///
+    /// A frame going missing while an atlas is being edited
+    /// keeps the old atlas, and `new` is remembered so that
+    /// fixing it and reloading again is picked up.
+    pub fn reload_atlas<F>(&mut self, display: &F, old: &Rc<TextureAtlas>, new: &Rc<TextureAtlas>) -> Result<(), MapError>
+        where F: Facade {
+        let rejected = self.rejected_atlas.as_ref().map(|x| Rc::ptr_eq(x, old)).unwrap_or(false);
+        if !Rc::ptr_eq(&self.atlas, old) && !rejected {
+            return Ok(());
+        }
+        let result = self.set_atlas(display, new.clone());
+        if result.is_err() {
+            self.rejected_atlas = Some(new.clone());
+        }
+        result
+    }
+
+    /// Rebuild the vertex and index buffers from the tiles.
+    pub fn rebuild<F>(&mut self, display: &F) -> Result<(), MapError>
+        where F: Facade {
+        let (vertex_buffer, index_buffers) = try!(build_buffers(
+            display, self.width, self.height,
+            self.tile_width, self.tile_height,
+            &self.tiles, &self.atlas));
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffers = index_buffers;
+        Ok(())
+    }
+
     /// Get the tile at the given indices
     pub fn get(&self, x: usize, y: usize) -> Option<&T> {
         self.tiles.get(self.width * y + x)
     }
 
+    /// The number of tiles drawn by `draw`, which is every
+    /// tile as the map isn't culled to the view.
+    #[inline]
+    pub fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// The number of draw calls made by `draw`, one for each
+    /// atlas page that has tiles on it.
+    pub fn batch_count(&self) -> usize {
+        self.index_buffers.iter().filter(|x| x.len() > 0).count()
+    }
+
     /// Iterate over the tiles in row order.
     pub fn iter(&self) -> ::std::slice::Iter<T> {
         self.tiles.iter()
@@ -187,13 +436,6 @@ impl<T: Default + Tile> TileMap<T> {
 
     pub fn draw<S>(&self, surface: &mut S, viewproj: &Mat4<f32>) 
         where S: Surface {
-        let sampled_texture = self.atlas.texture.sampled()
-            .minify_filter(MinifySamplerFilter::Nearest)
-            .magnify_filter(MagnifySamplerFilter::Nearest);
-        let uniforms = uniform! {
-            matrix: viewproj.clone(),
-            tex: sampled_texture
-        };
         let mut params = DrawParameters::default();
         params.blend = Blend::alpha_blending();
         params.depth = Depth {
@@ -201,12 +443,24 @@ impl<T: Default + Tile> TileMap<T> {
             write: true,
             .. Default::default()
         };
-        surface.draw(
-            &self.vertex_buffer,
-            &self.index_buffer,
-            &self.program,
-            &uniforms,
-            &params).unwrap();
+        for (page, index_buffer) in self.index_buffers.iter().enumerate() {
+            if index_buffer.len() == 0 {
+                continue;
+            }
+            let sampled_texture = self.atlas.texture(page).sampled()
+                .minify_filter(MinifySamplerFilter::Nearest)
+                .magnify_filter(MagnifySamplerFilter::Nearest);
+            let uniforms = uniform! {
+                matrix: viewproj.clone(),
+                tex: sampled_texture
+            };
+            surface.draw(
+                &self.vertex_buffer,
+                index_buffer,
+                &self.program,
+                &uniforms,
+                &params).unwrap();
+        }
     }
 }
 
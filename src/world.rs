@@ -0,0 +1,95 @@
+use na::Pnt2;
+
+use fnv::Fnv;
+use input::InputState;
+use rng::Rng;
+
+/// Camera movement speed in pixels per second.
+const CAMERA_SPEED: f32 = 180.0;
+
+/// The gameplay state that is advanced by the simulation.
+///
+/// Nothing in here touches the window or the GPU so that
+/// it can be stepped without one, e.g. when replaying a
+/// recording to check that it still ends up in the
+/// same place.
+pub struct World {
+    pub focus: Pnt2<f32>,
+    pub previous_focus: Pnt2<f32>,
+    pub rng: Rng,
+    ticks: u64
+}
+
+impl World {
+    pub fn new(seed: u64, focus: Pnt2<f32>) -> World {
+        World {
+            focus: focus,
+            previous_focus: focus,
+            rng: Rng::new(seed),
+            ticks: 0
+        }
+    }
+
+    #[inline]
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Advance the simulation by a single tick.
+    pub fn update(&mut self, input: &InputState, dt: f32) {
+        self.previous_focus = self.focus;
+
+        let speed = CAMERA_SPEED * dt;
+        self.focus.x += input.axis("horizontal") * speed;
+        self.focus.y += input.axis("vertical") * speed;
+
+        self.ticks += 1;
+    }
+
+    /// The focus blended between the last two ticks.
+    pub fn interpolated_focus(&self, alpha: f32) -> Pnt2<f32> {
+        Pnt2::new(
+            self.previous_focus.x + (self.focus.x - self.previous_focus.x) * alpha,
+            self.previous_focus.y + (self.focus.y - self.previous_focus.y) * alpha)
+    }
+
+    /// A hash of the simulation state.
+    ///
+    /// This uses FNV-1a rather than `std::hash` as it needs
+    /// to be stable across builds for stored replays.
+    pub fn hash(&self) -> u64 {
+        let mut hasher = Fnv::new();
+        hasher.write_u64(self.ticks);
+        hasher.write_u64(self.rng.state());
+        hasher.write_u32(self.focus.x.to_bits());
+        hasher.write_u32(self.focus.y.to_bits());
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use na::Pnt2;
+
+    use input::InputState;
+    use super::World;
+
+    #[test]
+    fn hash_depends_on_seed_and_input() {
+        let mut input = InputState::default();
+        input.axes.insert("horizontal".to_string(), 1.0);
+
+        let mut a = World::new(1, Pnt2::new(0.0, 0.0));
+        let mut b = World::new(1, Pnt2::new(0.0, 0.0));
+        assert_eq!(a.hash(), b.hash());
+        a.update(&input, 0.1);
+        b.update(&input, 0.1);
+        assert_eq!(a.hash(), b.hash());
+
+        b.update(&InputState::default(), 0.1);
+        a.update(&input, 0.1);
+        assert!(a.hash() != b.hash());
+
+        assert!(World::new(1, Pnt2::new(0.0, 0.0)).hash() != World::new(2, Pnt2::new(0.0, 0.0)).hash());
+    }
+}